@@ -0,0 +1,113 @@
+use game::{battle_state_hash, ValidationReport, save_battle, save_replay, BattleClock, BattleRng, BattleSummary, DifficultyMode, EndlessMode, GamePlugin, PlayerInput, PlayerInputs, ReplayMode, ReplayPlayback, ReplayPlugin, SaveMode, SavePlugin, TargetPriority};
+use game::prelude::*;
+
+//runs the battle without a window for a fixed amount of battle time or until it is won or lost
+//the same seed prints the same state hash on every run
+//  cargo run --example headless -- 30 42                      30 seconds with seed 42
+//  cargo run --example headless -- 30 42 --record a.ron       and save the battle as a replay
+//  cargo run --example headless -- 600 42 --endless endless   endless waves until the base falls
+//  cargo run --example headless -- 30 42 --difficulty hard     with the monsters and waves of a difficulty
+//  cargo run --example headless -- 30 42 --tower 3            build the main tower on build slot 3 in the first tick
+//  cargo run --example headless -- 30 42 --tower 3 --priority random   and switch it to another target priority once it is built
+//  cargo run --example headless -- 30 42 --tower 3 --upgrade main_rapid  and upgrade it once it is built
+//  cargo run --example headless -- 30 42 --save b.ron          save the battle when the time is up
+//  cargo run --example headless -- 60 0 --resume b.ron         go on with a saved battle until 60 seconds
+//  cargo run --example headless -- --replay a.ron             play a replay back until its final tick, panics if it diverges
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let mut app = App::new();
+    if args.get(0).map(|s| s.as_str()) == Some("--replay") {
+        app.add_plugin(GamePlugin { headless: true })
+            .add_plugin(ReplayPlugin { mode: ReplayMode::Play(args[1].clone()) });
+        while !app.world.get_resource::<ReplayPlayback>().unwrap().is_finished() {
+            app.update();
+            exit_on_broken_config(&app);
+        }
+        println!("replay matched after {} ticks", app.world.get_resource::<BattleClock>().unwrap().tick());
+        return;
+    }
+
+    let seconds = args.get(0).and_then(|s| s.parse().ok()).unwrap_or(10f64);
+    let seed = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(0u64);
+    let flag_value = |flag: &str| args.iter().position(|arg| arg == flag).map(|idx| args[idx + 1].clone());
+    app.insert_resource(BattleRng::new(seed));
+    if let Some(name) = flag_value("--difficulty") {
+        app.insert_resource(DifficultyMode { name });
+    }
+    if let Some(name) = flag_value("--endless") {
+        app.insert_resource(EndlessMode { name });
+    }
+    app.add_plugin(GamePlugin { headless: true });
+    if let Some(file) = flag_value("--resume") {
+        app.add_plugin(SavePlugin { mode: SaveMode::Resume(file) });
+    }
+    if let Some(slot) = flag_value("--tower").and_then(|s| s.parse().ok()) {
+        app.world.get_resource_mut::<PlayerInputs>().unwrap().push(PlayerInput::PlaceTower { attacker: sid!("main"), slot });
+    }
+    let record = flag_value("--record");
+    if let Some(file) = &record {
+        app.add_plugin(ReplayPlugin { mode: ReplayMode::Record(file.clone()) });
+    }
+    //the tower is only there after the tick it was placed in
+    let tower = flag_value("--tower").and_then(|s| s.parse::<usize>().ok());
+    if let (Some(slot), true) = (tower, args.iter().any(|arg| arg == "--priority" || arg == "--upgrade")) {
+        while app.world.query::<&TargetPriority>().iter(&app.world).next().is_none() {
+            app.update();
+        }
+        let mut inputs = app.world.get_resource_mut::<PlayerInputs>().unwrap();
+        if let Some(priority) = flag_value("--priority") {
+            inputs.push(PlayerInput::SetTargetPriority { slot, priority: parse_priority(&priority) });
+        }
+        if let Some(to) = flag_value("--upgrade") {
+            inputs.push(PlayerInput::UpgradeTower { slot, to: game::hashtoollib::hash(&to) });
+        }
+    }
+
+    let is_over = |app: &App| app.world.get_resource::<BattleSummary>().map_or(false, |s| s.outcome.is_some());
+    while app.world.get_resource::<BattleClock>().unwrap().elapsed_seconds() < seconds && !is_over(&app) {
+        app.update();
+        exit_on_broken_config(&app);
+    }
+
+    let count = app.world.query::<&Transform>().iter(&app.world).count();
+    let hash = battle_state_hash(&mut app.world);
+    let elapsed = app.world.get_resource::<BattleClock>().unwrap().elapsed_seconds();
+    println!("{} entities with a transform after {:.2}s, seed {}, state hash {:016x}", count, elapsed, seed, hash);
+    if let Some(summary) = app.world.get_resource::<BattleSummary>() {
+        println!("{:?}", summary);
+    }
+    if record.is_some() {
+        save_replay(&mut app.world).expect("failed to save replay");
+    }
+    if let Some(file) = flag_value("--save") {
+        save_battle(&mut app.world, &file).expect("failed to save battle");
+    }
+}
+
+//first, last, closest, random, strongest:<attr> or weakest:<attr>
+fn parse_priority(s: &str) -> TargetPriority {
+    let mut priority = match s.split_once(':') {
+        Some(("strongest", attr)) => TargetPriority::Strongest(StringId::Str(attr.to_string())),
+        Some(("weakest", attr)) => TargetPriority::Weakest(StringId::Str(attr.to_string())),
+        _ => {
+            match s {
+                "first" => TargetPriority::First,
+                "last" => TargetPriority::Last,
+                "closest" => TargetPriority::Closest,
+                "random" => TargetPriority::Random,
+                _ => panic!("unknown target priority {}", s),
+            }
+        }
+    };
+    priority.parse();
+    priority
+}
+
+//a broken config keeps the game loading, there is no editor to fix it here so give up
+fn exit_on_broken_config(app: &App) {
+    if let Some(report) = app.world.get_resource::<ValidationReport>().filter(|report| !report.is_ok()) {
+        eprintln!("{}", report);
+        std::process::exit(1);
+    }
+}
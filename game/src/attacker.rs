@@ -1,7 +1,19 @@
 use crate::prelude::*;
 use bevy::reflect::TypeUuid;
+use rand::Rng;
 use crate::StringId;
+use crate::attrs::Attrs;
+use crate::death::Death;
+use crate::effect;
+use crate::effect::EffectCommand;
+use crate::force::{CreateForceReq, ForceTarget};
+use crate::hit_query::HitQuery;
+use crate::monster::{Monster, MoveWithMapPath, MoveWithPathEnded};
+use crate::node::HierarchyNameMap;
+use crate::sim::{BattleClock, BattleRng, BattleStage, BattleSystem};
+use crate::stage::MapStage;
 use crate::table::TableData;
+use crate::view::SceneView;
 use super::table::TableDataItem;
 
 #[derive(serde::Deserialize, serde::Serialize, TypeUuid)]
@@ -10,6 +22,22 @@ pub struct AttackerConfig {
     pub abilities: Vec<AbilityConfig>,
     pub name: String,
     pub asset: String,
+    //gold spent to build it on a slot
+    #[serde(default)]
+    pub cost: u32,
+    //what a new tower aims at first, every placed tower can be switched on its own
+    #[serde(default)]
+    pub target_priority: TargetPriority,
+    //the entries a placed tower can be upgraded to, each of them lists the next step of the tree
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeConfig>,
+    //part of the gold spent on the tower and its upgrades that selling it gives back
+    #[serde(default = "default_sell_refund")]
+    pub sell_refund: f32,
+}
+
+fn default_sell_refund() -> f32 {
+    0.5f32
 }
 
 impl TableDataItem for AttackerConfig {
@@ -21,6 +49,114 @@ impl TableDataItem for AttackerConfig {
         for ac in &mut self.abilities {
             ac.parse();
         }
+        self.target_priority.parse();
+        for upgrade in &mut self.upgrades {
+            upgrade.to.change_2_id();
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct UpgradeConfig {
+    //the attacker entry the tower turns into, its asset and upgrades come with it
+    pub to: StringId,
+    pub cost: u32,
+    #[serde(default)]
+    pub abilities: UpgradeAbilities,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum UpgradeAbilities {
+    //the abilities of the new entry take the place of every ability the tower had
+    Replace,
+    //the tower keeps its abilities, with their ammo and targets, and gets the ones of the new entry on top
+    Add,
+}
+
+impl Default for UpgradeAbilities {
+    fn default() -> Self {
+        UpgradeAbilities::Replace
+    }
+}
+
+//which monster in range a tower picks, kept on the tower entity
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Component)]
+pub enum TargetPriority {
+    //the furthest along its road, the next one to leak
+    First,
+    //the least far along its road
+    Last,
+    //the most of an attr, e.g. Strongest(Str("hp"))
+    Strongest(StringId),
+    Weakest(StringId),
+    Closest,
+    //a new draw from BattleRng for every pick
+    Random,
+}
+
+impl Default for TargetPriority {
+    fn default() -> Self {
+        TargetPriority::Closest
+    }
+}
+
+impl TargetPriority {
+    pub fn parse(&mut self) {
+        if let TargetPriority::Strongest(attr) | TargetPriority::Weakest(attr) = self {
+            attr.change_2_id();
+        }
+    }
+
+    fn attr(&self) -> Option<u64> {
+        match self {
+            TargetPriority::Strongest(attr) | TargetPriority::Weakest(attr) => {
+                Some(attr.id())
+            }
+            _ => None,
+        }
+    }
+}
+
+//a monster in range as the priorities see it
+#[derive(Debug)]
+pub struct TargetCandidate {
+    pub entity: Entity,
+    //to the tower
+    pub distance: f32,
+    //left to walk along its road
+    pub remaining: f32,
+    //the attr of a Strongest or Weakest priority, 0 for a monster without it
+    pub strength: f32,
+}
+
+//the candidate the priority picks, on a tie the one that comes first
+pub fn select_target(priority: &TargetPriority, candidates: &[TargetCandidate], rng: &mut impl Rng) -> Option<Entity> {
+    let min_by = |key: fn(&TargetCandidate) -> f32| {
+        candidates.iter().min_by(|a, b| key(a).total_cmp(&key(b))).map(|c| c.entity)
+    };
+    match priority {
+        TargetPriority::First => {
+            min_by(|c| c.remaining)
+        }
+        TargetPriority::Last => {
+            min_by(|c| -c.remaining)
+        }
+        TargetPriority::Strongest(_) => {
+            min_by(|c| -c.strength)
+        }
+        TargetPriority::Weakest(_) => {
+            min_by(|c| c.strength)
+        }
+        TargetPriority::Closest => {
+            min_by(|c| c.distance)
+        }
+        TargetPriority::Random => {
+            //nothing to pick from draws nothing, so the stream only moves on when a tower actually picks
+            match candidates.is_empty() {
+                true => None,
+                false => Some(candidates[rng.gen_range(0..candidates.len())].entity),
+            }
+        }
     }
 }
 
@@ -35,15 +171,28 @@ impl AbilityConfig {
         match self {
             AbilityConfig::Shoot(s) => {
                 s.fire_node.change_2_id();
+                s.force.change_2_id();
             }
             AbilityConfig::Channel(c) => {
                 c.fire_node.change_2_id();
+                c.effect.change_2_id();
             }
         }
     }
 }
 
-pub struct Attacker {}
+//a placed tower, id is its entry in the attacker table and slot the build slot it stands on
+#[derive(Component)]
+pub struct Attacker {
+    pub id: u64,
+    pub slot: usize,
+    //monsters its hits dropped to 0, an upgrade keeps them
+    pub kills: u32,
+    //gold paid for the tower and its upgrades
+    pub spent: u32,
+    //the entries whose abilities it runs, in the order they were added
+    pub abilities: Vec<u64>,
+}
 
 pub struct AttackerRef {
     target: Entity,
@@ -55,11 +204,56 @@ pub struct ShootAbilityConfig {
     pub reload_time: f32,
     pub magazine: u32,
     pub fire_node: StringId,
+    //distance from the tower a target has to be in
+    pub range: f32,
+    //the force a shot fires at the target
+    pub force: StringId,
 }
 
+//the state of a shoot ability between ticks, read by the ui for the ammo and the cooldown
 #[derive(Component)]
 pub struct ShootAbilityRuntime {
     pub fire_node: Option<Entity>,
+    pub target: Option<Entity>,
+    pub ammo: u32,
+    //seconds until the next shot
+    pub cool_down: f32,
+    //seconds until the magazine is full again, 0 when not reloading
+    pub reload: f32,
+}
+
+impl ShootAbilityRuntime {
+    pub fn create(config: &ShootAbilityConfig) -> Self {
+        ShootAbilityRuntime { fire_node: None, target: None, ammo: config.magazine, cool_down: 0f32, reload: 0f32 }
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reload > 0f32
+    }
+
+    //advances the timers by delta, true when a shot is fired this tick
+    //the last shot of the magazine starts the reload, a shot is only fired at a target
+    pub fn update(&mut self, config: &ShootAbilityConfig, delta: f32, has_target: bool) -> bool {
+        self.cool_down = (self.cool_down - delta).max(0f32);
+        if self.is_reloading() {
+            self.reload = (self.reload - delta).max(0f32);
+            if self.is_reloading() {
+                return false;
+            }
+            self.ammo = config.magazine;
+        }
+
+        if !has_target || self.cool_down > 0f32 || self.ammo == 0 {
+            return false;
+        }
+
+        self.ammo -= 1;
+        self.cool_down = config.cd;
+        if self.ammo == 0 {
+            self.reload = config.reload_time;
+        }
+        true
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone, Component)]
@@ -67,52 +261,397 @@ pub struct ChannelAbilityConfig {
     pub total_value: f32,
     pub value_cost_speed: f32,
     pub fire_node: StringId,
+    pub range: f32,
+    //applied to the target when the beam locks on and then once every effect_interval seconds it stays on
+    pub effect: StringId,
+    pub effect_interval: f32,
+    //value per second coming back while the beam is off
+    pub regen_speed: f32,
 }
 
+//the state of a channel ability between ticks, a view draws the beam from the fire node to the target
 #[derive(Component)]
 pub struct ChannelAbilityRuntime {
     pub fire_node: Option<Entity>,
+    pub target: Option<Entity>,
+    //what is left to channel
+    pub value: f32,
+    //drained to 0, the beam only locks on again with a full value
+    pub exhausted: bool,
+    //beam time not turned into an effect yet
+    pub charge: f32,
+}
+
+impl ChannelAbilityRuntime {
+    pub fn create(config: &ChannelAbilityConfig) -> Self {
+        ChannelAbilityRuntime { fire_node: None, target: None, value: config.total_value, exhausted: false, charge: 0f32 }
+    }
+
+    //advances the value by delta with the target the beam is on this tick, returns how often the target is hit
+    //the hits only depend on the beam time, not on the step it is advanced by
+    //the tick that drains the value still hits, after it the beam is off until the value is full again
+    pub fn update(&mut self, config: &ChannelAbilityConfig, delta: f32, target: Option<Entity>) -> u32 {
+        let locked = self.target;
+        self.target = target.filter(|_| !self.exhausted);
+        match self.target {
+            Some(target) => {
+                if locked != Some(target) {
+                    self.charge = config.effect_interval;
+                }
+                self.charge += delta;
+                let hits = (self.charge / config.effect_interval).floor();
+                self.charge -= hits * config.effect_interval;
+
+                self.value -= config.value_cost_speed * delta;
+                if self.value <= 0f32 {
+                    self.value = 0f32;
+                    self.exhausted = true;
+                    self.target = None;
+                }
+                hits as u32
+            }
+            None => {
+                self.charge = 0f32;
+                self.value = (self.value + config.regen_speed * delta).min(config.total_value);
+                if self.value >= config.total_value {
+                    self.exhausted = false;
+                }
+                0
+            }
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct CreateAttackerReq {
-    id: u64,
+    pub id: u64,
+    pub slot: usize,
+    //the cost of the entry for a new tower, a resumed one brings what was paid for its upgrades too
+    pub spent: u32,
+    //the entries to take the abilities from, just id for a new tower
+    pub abilities: Vec<u64>,
+}
+
+//one child entity per ability of the config
+pub fn spawn_abilities(child_builder: &mut ChildBuilder, config: &AttackerConfig) {
+    for ability in &config.abilities {
+        let mut cmd = child_builder.spawn();
+        match ability {
+            AbilityConfig::Shoot(ab) => {
+                cmd.insert_bundle(
+                    (
+                        ab.clone(),
+                        ShootAbilityRuntime::create(ab)
+                    )
+                );
+            }
+
+            AbilityConfig::Channel(ab) => {
+                cmd.insert_bundle(
+                    (
+                        ab.clone(),
+                        ChannelAbilityRuntime::create(ab)
+                    )
+                );
+            }
+        }
+    }
 }
 
 pub fn spawn_attacker_system(mut commands: Commands,
                              query: Query<(Entity, &CreateAttackerReq)>,
-                             table: Res<TableData<AttackerConfig>>,
-                             asset_server: Res<AssetServer>) {
+                             table: Res<TableData<AttackerConfig>>) {
     for (entity, req) in query.iter() {
         let config = table.index(req.id);
-        commands.entity(entity).remove::<CreateAttackerReq>().with_children(|parent| {
-            parent.spawn_scene(asset_server.load(&config.asset));
-        }).with_children(|child_builder| {
-            for ability in &config.abilities {
-                let mut cmd = child_builder.spawn();
-                match ability {
-                    AbilityConfig::Shoot(ab) => {
-                        cmd.insert_bundle(
-                            (
-                                ab.clone(),
-                                ShootAbilityRuntime { fire_node: None }
-                            )
-                        );
-                    }
-
-                    AbilityConfig::Channel(ab) => {
-                        cmd.insert_bundle(
-                            (
-                                ab.clone(),
-                                ChannelAbilityRuntime { fire_node: None }
-                            )
-                        );
-                    }
-                }
+        let attacker = Attacker { id: req.id, slot: req.slot, kills: 0, spent: req.spent, abilities: req.abilities.clone() };
+        commands.entity(entity).remove::<CreateAttackerReq>().insert_bundle((attacker, config.target_priority.clone(), SceneView { asset: config.asset.clone() }, HierarchyNameMap::create())).with_children(|child_builder| {
+            for id in &req.abilities {
+                spawn_abilities(child_builder, table.index(*id));
             }
         });
     }
 }
 
-pub fn update_shoot_ability_system(query: Query<(&ShootAbilityConfig, &ShootAbilityRuntime)>) {}
+//the tower entity stays with its slot, kills and priority, replaced abilities go and the ones of the new entry come
+pub fn upgrade_attacker(commands: &mut Commands, tower: Entity, from: &AttackerConfig, to: &AttackerConfig, replaced: &[Entity]) {
+    for ability in replaced {
+        commands.entity(*ability).despawn_recursive();
+    }
+    let mut cmds = commands.entity(tower);
+    if from.asset != to.asset {
+        cmds.insert(SceneView { asset: to.asset.clone() });
+    }
+    cmds.with_children(|child_builder| spawn_abilities(child_builder, to));
+}
+
+pub struct AttackerPlugin;
+
+impl Plugin for AttackerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(BattleStage, update_shoot_ability_system.label(BattleSystem::Abilities).after(BattleSystem::HitQuery))
+            .add_system_to_stage(BattleStage, update_channel_ability_system.chain(effect::handle_effect_system).label(BattleSystem::Beams).after(BattleSystem::Abilities));
+    }
+}
+
+//monsters still walking their road, the ones that died or leaked in this tick are no target any more
+type TargetFilter = (With<Monster>, Without<MoveWithPathEnded>, Without<Death>);
+
+//the monsters in range in entity order, with what the priority needs to know about them
+fn target_candidates(center: Vec3,
+                     range: f32,
+                     priority: &TargetPriority,
+                     hit_query: &HitQuery,
+                     targets: &Query<(&MoveWithMapPath, &Attrs), TargetFilter>,
+                     stage: &MapStage) -> Vec<TargetCandidate> {
+    let attr = priority.attr();
+    hit_query.overlap_sphere(center, range).into_iter().filter_map(|hr| {
+        let (move_with, attrs) = targets.get(hr.entity).ok()?;
+        let position = hr.position();
+        Some(TargetCandidate {
+            entity: hr.entity,
+            distance: position.distance(center),
+            remaining: stage.roads[move_with.road_index].remaining_distance(move_with.target_point_index, position),
+            strength: attr.and_then(|attr| attrs.get(attr)).unwrap_or(0f32),
+        })
+    }).collect()
+}
+
+//looked up on every tick, an upgrade to another asset brings a new scene with its own fire node
+fn resolve_fire_node(attacker: Entity, fire_node: u64, node: &mut Option<Entity>, names: &Query<&HierarchyNameMap>) {
+    *node = names.get(attacker).ok().and_then(|map| map.values.get(&fire_node).copied());
+}
+
+//the fire node of the tower scene once it is loaded, the tower itself until then and in headless battles
+//the scene only comes with a window, so battles with and without one fire from different points
+fn fire_position(attacker: Entity,
+                 center: Vec3,
+                 fire_node: u64,
+                 node: &mut Option<Entity>,
+                 names: &Query<&HierarchyNameMap>,
+                 transforms: &Query<&GlobalTransform>) -> Vec3 {
+    resolve_fire_node(attacker, fire_node, node, names);
+    node.and_then(|e| transforms.get(e).ok()).map_or(center, |t| t.translation)
+}
+
+pub fn update_shoot_ability_system(mut commands: Commands,
+                                   mut query: Query<(&Parent, &ShootAbilityConfig, &mut ShootAbilityRuntime)>,
+                                   priorities: Query<&TargetPriority>,
+                                   names: Query<&HierarchyNameMap>,
+                                   transforms: Query<&GlobalTransform>,
+                                   targets: Query<(&MoveWithMapPath, &Attrs), TargetFilter>,
+                                   hit_query: Res<HitQuery>,
+                                   stage: Res<MapStage>,
+                                   clock: Res<BattleClock>,
+                                   mut rng: ResMut<BattleRng>) {
+    for (parent, config, mut runtime) in query.iter_mut() {
+        let (center, priority) = match (transforms.get(parent.0), priorities.get(parent.0)) {
+            (Ok(t), Ok(priority)) => (t.translation, priority),
+            _ => continue,
+        };
+
+        let candidates = target_candidates(center, config.range, priority, &hit_query, &targets, &stage);
+        let fire = runtime.update(config, clock.delta_seconds(), !candidates.is_empty());
+        if candidates.is_empty() {
+            runtime.target = None;
+        }
+        if !fire {
+            continue;
+        }
+
+        //every shot picks again, so a tower switched to another priority follows it from its next shot
+        runtime.target = select_target(priority, &candidates, &mut *rng);
+        let target = runtime.target;
+
+        let position = fire_position(parent.0, center, config.fire_node.id(), &mut runtime.fire_node, &names, &transforms);
+        commands.spawn_bundle((
+            Transform::from_translation(position),
+            GlobalTransform::from_translation(position),
+            CreateForceReq { id: config.force.id(), source: Some(parent.0) },
+            ForceTarget::Entity(target.unwrap()),
+        ));
+    }
+}
+
+
+//the beam hits its target directly, the fire node is only resolved for the view to draw the beam from
+pub fn update_channel_ability_system(mut query: Query<(&Parent, &ChannelAbilityConfig, &mut ChannelAbilityRuntime)>,
+                                     priorities: Query<&TargetPriority>,
+                                     names: Query<&HierarchyNameMap>,
+                                     transforms: Query<&GlobalTransform>,
+                                     targets: Query<(&MoveWithMapPath, &Attrs), TargetFilter>,
+                                     hit_query: Res<HitQuery>,
+                                     stage: Res<MapStage>,
+                                     clock: Res<BattleClock>,
+                                     mut rng: ResMut<BattleRng>) -> Vec<EffectCommand> {
+    let mut cmds = Vec::new();
+    for (parent, config, mut runtime) in query.iter_mut() {
+        let (center, priority) = match (transforms.get(parent.0), priorities.get(parent.0)) {
+            (Ok(t), Ok(priority)) => (t.translation, priority),
+            _ => continue,
+        };
+        let runtime = &mut *runtime;
+        resolve_fire_node(parent.0, config.fire_node.id(), &mut runtime.fire_node, &names);
+
+        let candidates = target_candidates(center, config.range, priority, &hit_query, &targets, &stage);
+        let target = match runtime.target {
+            //the beam breaks when its target leaves the range or dies, it looks for another one in the next tick
+            //a locked beam stays on its target, a changed priority only counts for the next lock
+            Some(target) => {
+                candidates.iter().any(|c| c.entity == target).then(|| target)
+            }
+            None if !runtime.exhausted => {
+                select_target(priority, &candidates, &mut *rng)
+            }
+            None => None,
+        };
+
+        for _ in 0..runtime.update(config, clock.delta_seconds(), target) {
+            cmds.push(EffectCommand { target: target.unwrap(), id: config.effect.id(), source: Some(parent.0) });
+        }
+    }
+    cmds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //quarter seconds add up exactly, so the timers end on a tick
+    const STEP: f32 = 0.25f32;
+
+    fn config() -> ShootAbilityConfig {
+        ShootAbilityConfig { cd: 1f32, reload_time: 2f32, magazine: 3, fire_node: StringId::Id(0), range: 5f32, force: StringId::Id(0) }
+    }
 
+    //the ticks a shot is fired on while there always is a target
+    fn shots(runtime: &mut ShootAbilityRuntime, ticks: u32) -> Vec<u32> {
+        (1..=ticks).filter(|_| runtime.update(&config(), STEP, true)).collect()
+    }
+
+    #[test]
+    fn fires_on_cool_down_and_reloads_an_empty_magazine() {
+        let mut runtime = ShootAbilityRuntime::create(&config());
+
+        //three shots a second apart, then 2 seconds of reload before the next one
+        assert_eq!(shots(&mut runtime, 24), vec![1, 5, 9, 17, 21]);
+        assert_eq!(runtime.ammo, 1);
+        assert!(!runtime.is_reloading());
+    }
+
+    #[test]
+    fn waits_for_a_target_without_spending_ammo() {
+        let mut runtime = ShootAbilityRuntime::create(&config());
+        for _ in 0..8 {
+            assert!(!runtime.update(&config(), STEP, false));
+        }
+        assert_eq!(runtime.ammo, 3);
+
+        //the cool down ran out while waiting, so the first target is shot right away
+        assert!(runtime.update(&config(), STEP, true));
+        assert_eq!(runtime.cool_down, 1f32);
+    }
+
+    fn channel() -> ChannelAbilityConfig {
+        ChannelAbilityConfig { total_value: 2f32, value_cost_speed: 1f32, fire_node: StringId::Id(0), range: 4f32, effect: StringId::Id(0), effect_interval: 0.5f32, regen_speed: 0.5f32 }
+    }
+
+    #[test]
+    fn beam_drains_the_value_and_rests_until_it_is_full() {
+        let mut runtime = ChannelAbilityRuntime::create(&channel());
+        let target = Some(Entity::from_raw(1));
+
+        //two seconds of beam, a hit on locking and one every half second, the tick that drains the value still hits
+        let hits = (1..=12).map(|_| runtime.update(&channel(), STEP, target)).sum::<u32>();
+        assert_eq!(hits, 5);
+        assert!(runtime.exhausted);
+        assert_eq!(runtime.value, 0.5f32);
+        assert_eq!(runtime.target, None);
+
+        //back to full after 4 seconds of rest, then the beam locks on again
+        for _ in 0..12 {
+            assert_eq!(runtime.update(&channel(), STEP, target), 0);
+        }
+        assert!(!runtime.exhausted);
+        assert_eq!(runtime.value, 2f32);
+        assert_eq!(runtime.update(&channel(), STEP, target), 1);
+        assert_eq!(runtime.target, target);
+    }
+
+    #[test]
+    fn beam_regenerates_without_a_target() {
+        let mut runtime = ChannelAbilityRuntime::create(&channel());
+        for _ in 0..4 {
+            runtime.update(&channel(), STEP, Some(Entity::from_raw(1)));
+        }
+        assert_eq!(runtime.value, 1f32);
+
+        //a beam that is not exhausted locks on again right away
+        assert_eq!(runtime.update(&channel(), STEP, None), 0);
+        assert_eq!(runtime.value, 1.125f32);
+        assert_eq!(runtime.update(&channel(), STEP, Some(Entity::from_raw(2))), 1);
+        assert_eq!(runtime.value, 0.875f32);
+    }
+
+    #[test]
+    fn beam_hits_as_often_at_any_step() {
+        let config = ChannelAbilityConfig { effect_interval: 0.125f32, ..channel() };
+        let hits = |step: f32| {
+            let mut runtime = ChannelAbilityRuntime::create(&config);
+            (0..(1f32 / step).round() as u32).map(|_| runtime.update(&config, step, Some(Entity::from_raw(1)))).sum::<u32>()
+        };
+
+        //one second of beam, the hit on locking and one every 1/8 second
+        assert_eq!(hits(0.25f32), 9);
+        assert_eq!(hits(1f32 / 32f32), 9);
+        //a step that does not add up exactly is off by one hit at most
+        assert!((hits(1f32 / 60f32) as i32 - 9).abs() <= 1);
+    }
+
+    //(distance, remaining, strength) of monsters 0, 1, 2 and 3
+    fn candidates() -> Vec<TargetCandidate> {
+        [(3f32, 12f32, 80f32), (1f32, 20f32, 10f32), (4f32, 2f32, 30f32), (2f32, 9f32, 80f32)].iter().enumerate()
+            .map(|(idx, (distance, remaining, strength))| TargetCandidate { entity: Entity::from_raw(idx as u32), distance: *distance, remaining: *remaining, strength: *strength })
+            .collect()
+    }
+
+    fn pick(priority: TargetPriority) -> Option<u32> {
+        select_target(&priority, &candidates(), &mut BattleRng::new(0)).map(|e| e.id())
+    }
+
+    #[test]
+    fn priorities_pick_by_progress_attr_and_distance() {
+        assert_eq!(pick(TargetPriority::First), Some(2));
+        assert_eq!(pick(TargetPriority::Last), Some(1));
+        assert_eq!(pick(TargetPriority::Weakest(StringId::Id(0))), Some(1));
+        assert_eq!(pick(TargetPriority::Closest), Some(1));
+        //0 and 3 are as strong, the first one in entity order wins
+        assert_eq!(pick(TargetPriority::Strongest(StringId::Id(0))), Some(0));
+    }
+
+    #[test]
+    fn random_picks_every_candidate_and_draws_nothing_without_one() {
+        let mut rng = BattleRng::new(7);
+        assert_eq!(select_target(&TargetPriority::Random, &[], &mut rng), None);
+        assert_eq!(rng.word_pos(), 0);
+
+        let mut picked = (0..64).filter_map(|_| select_target(&TargetPriority::Random, &candidates(), &mut rng)).map(|e| e.id()).collect::<Vec<_>>();
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked, vec![0, 1, 2, 3]);
+
+        //the same seed picks the same monsters
+        let draws = |seed| {
+            let mut rng = BattleRng::new(seed);
+            (0..8).map(|_| select_target(&TargetPriority::Random, &candidates(), &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(3), draws(3));
+    }
+
+    #[test]
+    fn priority_attr_names_are_parsed() {
+        let mut priority: TargetPriority = ron::de::from_str(r#"Strongest(Str("hp"))"#).unwrap();
+        priority.parse();
+        assert_eq!(priority, TargetPriority::Strongest(StringId::Id(hashtoollib::hash("hp"))));
+        assert_eq!(TargetPriority::default(), TargetPriority::Closest);
+    }
+}
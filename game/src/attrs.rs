@@ -4,6 +4,7 @@ use crossbeam_queue::SegQueue;
 use crate::death::Death;
 use crate::game::GameState;
 use crate::prelude::*;
+use crate::sim::{BattleStage, BattleSystem};
 use serde::{Serialize, Deserialize};
 
 #[derive(Deserialize, Serialize)]
@@ -48,10 +49,44 @@ impl Attrs {
             values
         }
     }
+
+    //keep the current values, only pick up new attrs and max relations from the reloaded config
+    pub fn refresh_from_config(&mut self, config: &AttrsConfig) {
+        for c in &config.values {
+            let attr = self.values.entry(c.name.id()).or_insert(Attr { value: c.init, max_attr: None });
+            attr.max_attr = c.max.as_id();
+        }
+    }
+
+    //puts back saved values, an attr the config no longer has keeps no max
+    pub fn restore_values(&mut self, values: &[(u64, f32)]) {
+        for (id, value) in values {
+            self.values.entry(*id).or_insert(Attr { value: 0f32, max_attr: None }).value = *value;
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<f32> {
+        self.values.get(&id).map(|attr| attr.value)
+    }
+
+    pub fn scale(&mut self, ids: &[u64], factor: f32) {
+        for id in ids {
+            if let Some(attr) = self.values.get_mut(id) {
+                attr.value *= factor;
+            }
+        }
+    }
+
+    pub fn sorted_values(&self) -> Vec<(u64, f32)> {
+        let mut values = self.values.iter().map(|(id, attr)| (*id, attr.value)).collect::<Vec<_>>();
+        values.sort_by_key(|(id, _)| *id);
+        values
+    }
 }
 
 pub enum AttrCommand {
-    Add(Entity, u64, f32)
+    //target, attr, value and the tower it comes from, which gets the kill if the attr drops to 0
+    Add(Entity, u64, f32, Option<Entity>)
 }
 
 pub struct AttrCommandQueue {
@@ -71,7 +106,7 @@ impl Plugin for AttrPlugin {
         app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(setup_attr_system));
         app.add_system_set(SystemSet::on_exit(GameState::Playing).with_system(destroy_attr_system));
 
-        app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_attr_system));
+        app.add_system_to_stage(BattleStage, update_attr_system.label(BattleSystem::Attrs).after(BattleSystem::Forces));
     }
 }
 
@@ -90,24 +125,30 @@ fn destroy_attr_system(mut commands: Commands) {
 fn update_attr_system(mut commands: Commands, queue: Res<AttrCommandQueue>, mut query: Query<(&mut Attrs)>) {
     while let Some(cmd) = queue.seg.pop() {
         match cmd {
-            AttrCommand::Add(entity, name, value) => {
-                let mut attrs = query.get_mut(entity).expect(format!("failed to find attrs on entity {:?}", entity).as_str());
+            AttrCommand::Add(entity, name, value, source) => {
+                //the entity was despawned after the command was pushed, e.g. two forces killed it in the same tick
+                let mut attrs = match query.get_mut(entity) {
+                    Ok(attrs) => attrs,
+                    Err(_) => continue,
+                };
 
                 match attrs.values.entry(name) {
 
                     Entry::Occupied(o) => {
                         let attr = o.into_mut();
-                        let mut v = attr.value + value;
+                        let old = attr.value;
+                        let mut v = old + value;
                         let max_attr = attr.max_attr;
                         let attr_p: *mut Attr = attr;
 
                         if let Some(m) = max_attr {
                             let max = attrs.values.get(&m).expect(format!("failed to get max value of {}", m).as_str());
-                            v = v.max(max.value);
+                            v = v.min(max.value);
                         }
 
-                        if v <= 0f32 {
-                            commands.entity(entity).insert(Death {});
+                        //only the hit that drops it to 0 kills, later hits in the same tick do not take the kill over
+                        if v <= 0f32 && old > 0f32 {
+                            commands.entity(entity).insert(Death { killer: source });
                         }
 
                         unsafe {
@@ -129,4 +170,61 @@ fn update_attr_system(mut commands: Commands, queue: Res<AttrCommandQueue>, mut
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effect::{handle_effect_system, EffectCommand, EffectsConfig};
+    use crate::table::TableData;
+
+    fn world() -> (World, Entity) {
+        let mut config: AttrsConfig = ron::de::from_str(r#"(values: [(name: Str("hp"), init: 10, max: Some(Str("hp_max"))), (name: Str("hp_max"), init: 10, max: None)])"#).unwrap();
+        config.parse();
+        let mut world = World::new();
+        world.insert_resource(AttrCommandQueue { seg: SegQueue::new() });
+        let entity = world.spawn().insert(Attrs::load_from_config(&config)).id();
+        (world, entity)
+    }
+
+    fn update(world: &mut World, cmds: Vec<AttrCommand>) {
+        for cmd in cmds {
+            world.get_resource::<AttrCommandQueue>().unwrap().push(cmd);
+        }
+        let mut system = update_attr_system.system();
+        system.initialize(world);
+        system.run((), world);
+        system.apply_buffers(world);
+    }
+
+    fn hp(world: &World, entity: Entity) -> Option<f32> {
+        world.get::<Attrs>(entity).unwrap().get(sid!("hp"))
+    }
+
+    #[test]
+    fn values_are_capped_by_their_max() {
+        let (mut world, entity) = world();
+        update(&mut world, vec![AttrCommand::Add(entity, sid!("hp"), -4f32, None)]);
+        assert_eq!(hp(&world, entity), Some(6f32));
+        update(&mut world, vec![AttrCommand::Add(entity, sid!("hp"), 6f32, None)]);
+        assert_eq!(hp(&world, entity), Some(10f32));
+    }
+
+    #[test]
+    fn the_hit_dropping_to_zero_takes_the_kill() {
+        let (mut world, entity) = world();
+        let (first, second) = (world.spawn().id(), world.spawn().id());
+        update(&mut world, vec![AttrCommand::Add(entity, sid!("hp"), -10f32, Some(first)), AttrCommand::Add(entity, sid!("hp"), -5f32, Some(second))]);
+        assert_eq!(world.get::<Death>(entity).unwrap().killer, Some(first));
+    }
+
+    #[test]
+    fn hurt_takes_hp_away() {
+        let (mut world, entity) = world();
+        world.insert_resource(TableData::<EffectsConfig>::try_load_from_bytes(br#"[(name: Str("hurt"), values: [Hurt(3)])]"#, "effects").unwrap());
+        let mut system = handle_effect_system.system();
+        system.initialize(&mut world);
+        system.run(vec![EffectCommand { id: sid!("hurt"), target: entity, source: None }], &mut world);
+        update(&mut world, Vec::new());
+        assert_eq!(hp(&world, entity), Some(7f32));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::game::GameState;
+use crate::monster::{Monster, MonsterConfig, MoveWithPathEnded};
+use crate::prelude::*;
+use crate::sim::{BattleClock, BattleStage, BattleSystem};
+use crate::stage::MapStage;
+use crate::table::TableData;
+use crate::death;
+
+pub const DEFAULT_LIVES: u32 = 20;
+pub const DEFAULT_GOLD: u32 = 100;
+
+//the base the monsters walk to, every monster reaching the end of its road costs its leak damage
+pub struct BattleBase {
+    pub lives: u32,
+    pub gold: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BattleOutcome {
+    Victory,
+    Defeat,
+}
+
+//counted while playing, the outcome is set when the battle ends and the summary stays for the Result state
+#[derive(Default, Debug)]
+pub struct BattleSummary {
+    pub outcome: Option<BattleOutcome>,
+    pub kills: u32,
+    pub leaks: u32,
+    pub lives_left: u32,
+    pub seconds: f64,
+}
+
+pub struct BattlePlugin;
+
+impl Plugin for BattlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(setup_battle_system))
+            .add_system_set(SystemSet::on_enter(GameState::Result).with_system(report_battle_system))
+            .add_system_to_stage(BattleStage, death::death_system.label(BattleSystem::Death).after(BattleSystem::Attrs))
+            .add_system_to_stage(BattleStage, leak_system.label(BattleSystem::Leak).after(BattleSystem::Death))
+            .add_system_to_stage(BattleStage, check_battle_end_system.label(BattleSystem::Outcome).after(BattleSystem::Leak));
+    }
+}
+
+fn setup_battle_system(mut commands: Commands) {
+    commands.insert_resource(BattleBase { lives: DEFAULT_LIVES, gold: DEFAULT_GOLD });
+    commands.insert_resource(BattleSummary::default());
+}
+
+fn leak_system(mut commands: Commands,
+               query: Query<(Entity, &Monster), Added<MoveWithPathEnded>>,
+               table: Res<TableData<MonsterConfig>>,
+               mut base: ResMut<BattleBase>,
+               mut summary: ResMut<BattleSummary>) {
+    for (entity, monster) in query.iter() {
+        let damage = table.find(monster.id).map_or(1, |config| config.leak_damage);
+        base.lives = base.lives.saturating_sub(damage);
+        summary.leaks += 1;
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+//victory needs every queue finished and the last monster gone, losing the last life is a defeat right away
+fn check_battle_end_system(mut state: ResMut<State<GameState>>,
+                           stage: Res<MapStage>,
+                           monsters: Query<(), With<Monster>>,
+                           base: Res<BattleBase>,
+                           clock: Res<BattleClock>,
+                           mut summary: ResMut<BattleSummary>) {
+    if summary.outcome.is_some() {
+        return;
+    }
+
+    let outcome = if base.lives == 0 {
+        BattleOutcome::Defeat
+    } else if stage.is_finished() && monsters.is_empty() {
+        BattleOutcome::Victory
+    } else {
+        return;
+    };
+
+    summary.outcome = Some(outcome);
+    summary.lives_left = base.lives;
+    summary.seconds = clock.elapsed_seconds();
+    state.set(GameState::Result).expect(format!("failed to switch game state to {:?}", GameState::Result).as_str());
+}
+
+fn report_battle_system(summary: Res<BattleSummary>) {
+    info!("battle {:?} after {:.1}s, {} kills, {} leaks, {} lives left",
+        summary.outcome.unwrap(), summary.seconds, summary.kills, summary.leaks, summary.lives_left);
+}
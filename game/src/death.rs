@@ -1,10 +1,26 @@
+use crate::attacker::Attacker;
+use crate::battle::BattleSummary;
+use crate::monster::Monster;
 use crate::prelude::*;
 
 #[derive(Component)]
-pub struct Death {}
+pub struct Death {
+    //the tower whose hit dropped it to 0, None for anything else
+    pub killer: Option<Entity>,
+}
 
-pub fn death_system(mut commands: Commands, query: Query<Entity, With<Death>>) {
-    for entity in query.iter() {
+pub fn death_system(mut commands: Commands,
+                    query: Query<(Entity, Option<&Monster>, &Death)>,
+                    mut attackers: Query<&mut Attacker>,
+                    mut summary: ResMut<BattleSummary>) {
+    for (entity, monster, death) in query.iter() {
+        if monster.is_some() {
+            summary.kills += 1;
+            //a tower sold in the tick its shot landed gets nothing
+            if let Some(mut attacker) = death.killer.and_then(|killer| attackers.get_mut(killer).ok()) {
+                attacker.kills += 1;
+            }
+        }
         commands.entity(entity).despawn_recursive();
     }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+use crate::attrs::Attrs;
+use crate::prelude::*;
+use crate::table::TableDataItem;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DifficultyAttrConfig {
+    pub name: StringId,
+    pub scale: f32,
+}
+
+//multipliers over the monster table and the waves of the map, a missing one leaves the value as it is
+#[derive(Deserialize, Serialize, TypeUuid, Clone)]
+#[uuid = "a83f1e52-6c0d-4b97-b2e4-19d5c7a06f3e"]
+pub struct DifficultyConfig {
+    pub name: String,
+    #[serde(default = "default_scale")]
+    pub move_speed: f32,
+    //scales the initial value of each listed attr
+    #[serde(default)]
+    pub attrs: Vec<DifficultyAttrConfig>,
+    #[serde(default = "default_scale")]
+    pub per_spawn_count: f32,
+    #[serde(default = "default_scale")]
+    pub spawn_cool_down: f32,
+}
+
+fn default_scale() -> f32 {
+    1f32
+}
+
+impl Default for DifficultyConfig {
+    fn default() -> Self {
+        DifficultyConfig {
+            name: String::new(),
+            move_speed: 1f32,
+            attrs: Vec::new(),
+            per_spawn_count: 1f32,
+            spawn_cool_down: 1f32,
+        }
+    }
+}
+
+impl TableDataItem for DifficultyConfig {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn parse(&mut self) {
+        for attr in &mut self.attrs {
+            attr.name.change_2_id();
+        }
+    }
+}
+
+impl DifficultyConfig {
+    pub fn scale_attrs(&self, attrs: &mut Attrs) {
+        for attr in &self.attrs {
+            attrs.scale(&[attr.name.id()], attr.scale);
+        }
+    }
+
+    //a wave always spawns at least one unit at a time
+    pub fn scale_per_spawn_count(&self, count: u32) -> u32 {
+        (count as f32 * self.per_spawn_count).round().max(1f32) as u32
+    }
+}
+
+//picked with the name of a DifficultyConfig before the battle starts, without it the battle plays the tables as they are
+//the picked config is a resource of its own while playing
+pub struct DifficultyMode {
+    pub name: String,
+}
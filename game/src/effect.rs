@@ -1,3 +1,4 @@
+use bevy::reflect::TypeUuid;
 use serde::{Serialize, Deserialize};
 use crate::attrs::{AttrCommand, AttrCommandQueue};
 use crate::prelude::*;
@@ -9,7 +10,8 @@ pub enum EffectConfig {
     Hurt(f32),
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, TypeUuid)]
+#[uuid = "b7e2d8a4-51c6-4f3b-8e19-6a0f4c2d9e13"]
 pub struct EffectsConfig {
     pub name: StringId,
     pub values: Vec<EffectConfig>,
@@ -28,6 +30,8 @@ impl TableDataItem for EffectsConfig {
 pub struct EffectCommand {
     pub id: u64,
     pub target: Entity,
+    //the tower behind the effect, if any
+    pub source: Option<Entity>,
 }
 
 pub fn handle_effect_system(In(cmds): In<Vec<EffectCommand>>,
@@ -40,7 +44,7 @@ pub fn handle_effect_system(In(cmds): In<Vec<EffectCommand>>,
             match cfg {
                 EffectConfig::Hurt(hurt_value) => {
                     attr_commands.push(AttrCommand::Add(
-                        cmd.target, 0, *hurt_value,
+                        cmd.target, sid!("hp"), -*hurt_value, cmd.source,
                     ))
                 }
             }
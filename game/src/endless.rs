@@ -0,0 +1,187 @@
+use bevy::reflect::TypeUuid;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+use crate::proto::PathEditor::Wave;
+use crate::table::TableDataItem;
+
+//the waves the generator keeps ready ahead of the running one, so the preview always has something to show
+pub const ENDLESS_LOOKAHEAD: usize = 3;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct EndlessUnitConfig {
+    pub unit: StringId,
+    //budget one unit takes out of a wave
+    pub cost: f32,
+    //the unit only shows up after this many minutes
+    #[serde(default)]
+    pub from_minute: f32,
+}
+
+//budget and attr scale are interpolated between the points, past the last point the last slope goes on
+#[derive(Deserialize, Serialize, Clone)]
+pub struct EndlessCurvePoint {
+    pub minute: f32,
+    pub budget: f32,
+    pub attr_scale: f32,
+}
+
+#[derive(Deserialize, Serialize, TypeUuid, Clone)]
+#[uuid = "5f2d7c81-9b3e-4a6d-8c15-e3a7b90d4f26"]
+pub struct EndlessConfig {
+    pub name: String,
+    //seconds before the first wave and between the starts of two waves
+    pub first_wave: f32,
+    pub wave_interval: f32,
+    pub spawn_cool_down: f32,
+    pub per_spawn_count: u32,
+    #[serde(default)]
+    pub early_call_reward: f32,
+    pub units: Vec<EndlessUnitConfig>,
+    pub curve: Vec<EndlessCurvePoint>,
+    //indices into the paths of the map, empty allows every path
+    #[serde(default)]
+    pub paths: Vec<usize>,
+    //attrs multiplied by the attr scale of the curve, e.g. hp and hp_max
+    #[serde(default)]
+    pub scaled_attrs: Vec<StringId>,
+}
+
+impl TableDataItem for EndlessConfig {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn parse(&mut self) {
+        for unit in &mut self.units {
+            unit.unit.change_2_id();
+        }
+        for attr in &mut self.scaled_attrs {
+            attr.change_2_id();
+        }
+    }
+}
+
+impl EndlessConfig {
+    //every listed path has to be on the map, an empty list needs the map to have at least one
+    pub fn fits_paths(&self, path_count: usize) -> bool {
+        match self.paths.is_empty() {
+            true => {
+                path_count > 0
+            }
+            false => {
+                self.paths.iter().all(|path| *path < path_count)
+            }
+        }
+    }
+
+    //(budget, attr scale) at the given minute of the queue
+    pub fn sample(&self, minute: f32) -> (f32, f32) {
+        let points = &self.curve;
+        match points.len() {
+            0 => {
+                (0f32, 1f32)
+            }
+            1 => {
+                (points[0].budget, points[0].attr_scale)
+            }
+            _ => {
+                let idx = points.iter().skip(1).position(|p| minute < p.minute).unwrap_or(points.len() - 2);
+                let (a, b) = (&points[idx], &points[idx + 1]);
+                let t = (minute.max(points[0].minute) - a.minute) / (b.minute - a.minute).max(f32::EPSILON);
+                (a.budget + (b.budget - a.budget) * t, a.attr_scale + (b.attr_scale - a.attr_scale) * t)
+            }
+        }
+    }
+}
+
+//picked with the name of an EndlessConfig before the battle starts, the map then only lends its paths
+pub struct EndlessMode {
+    pub name: String,
+}
+
+//makes the waves of an endless queue one after the other, the seed comes from BattleRng so a replay repeats them
+pub struct EndlessWaves {
+    config: EndlessConfig,
+    paths: Vec<usize>,
+    seed: u64,
+    rng: StdRng,
+    generated: usize,
+}
+
+impl EndlessWaves {
+    //the config needs at least one unit and paths on the map, the validator and init_stage_system keep the others out
+    pub fn new(config: &EndlessConfig, path_count: usize, seed: u64) -> Self {
+        assert!(!config.units.is_empty(), "endless config {} has no units", config.name);
+        assert!(config.fits_paths(path_count), "endless config {} has no paths on a map with {} paths", config.name, path_count);
+        let paths = match config.paths.is_empty() {
+            true => {
+                (0..path_count).collect()
+            }
+            false => {
+                config.paths.clone()
+            }
+        };
+
+        EndlessWaves { config: config.clone(), paths, seed, rng: StdRng::seed_from_u64(seed), generated: 0 }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn generated(&self) -> usize {
+        self.generated
+    }
+
+    //back to the first wave of seed, generating the same count of waves again gets to the same state
+    pub fn restart(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self.generated = 0;
+    }
+
+    pub fn scaled_attrs(&self) -> Vec<u64> {
+        self.config.scaled_attrs.iter().map(|attr| attr.id()).collect()
+    }
+
+    //the wave and its attr scale, its wait time counts from the previous wave
+    pub fn next_wave(&mut self) -> (Wave, f32) {
+        let start_time = self.config.first_wave + self.generated as f32 * self.config.wave_interval;
+        let minute = start_time / 60f32;
+        let (budget, attr_scale) = self.config.sample(minute);
+
+        let unlocked = self.config.units.iter().filter(|u| u.from_minute <= minute).collect::<Vec<_>>();
+        let affordable = unlocked.iter().filter(|u| u.cost <= budget).copied().collect::<Vec<_>>();
+        //a budget too small for every unit still sends the cheapest one
+        let unit = match (affordable.is_empty(), unlocked.is_empty()) {
+            (false, _) => {
+                affordable[self.rng.gen_range(0..affordable.len())]
+            }
+            (true, false) => {
+                unlocked.iter().copied().min_by(|a, b| a.cost.total_cmp(&b.cost)).unwrap()
+            }
+            (true, true) => {
+                self.config.units.iter().min_by(|a, b| a.from_minute.total_cmp(&b.from_minute)).unwrap()
+            }
+        };
+
+        let count = (budget / unit.cost.max(f32::EPSILON)).floor().max(1f32) as u32;
+        let per_spawn_count = self.config.per_spawn_count.max(1);
+        let spawns = (count + per_spawn_count - 1) / per_spawn_count;
+        let path_index = self.paths[self.rng.gen_range(0..self.paths.len())];
+
+        let wave = Wave {
+            wait_time: if self.generated == 0 { self.config.first_wave } else { self.config.wave_interval },
+            unit: unit.unit.id(),
+            spawn_cool_down: self.config.spawn_cool_down,
+            duration: spawns as f32 * self.config.spawn_cool_down,
+            per_spawn_count: per_spawn_count as i32,
+            path_index: path_index as i32,
+            early_call_reward: self.config.early_call_reward,
+        };
+        self.generated += 1;
+        (wave, attr_scale)
+    }
+}
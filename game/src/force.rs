@@ -1,4 +1,5 @@
 use std::ops::{Deref, DerefMut};
+use bevy::reflect::TypeUuid;
 use itertools::Itertools;
 use bvh::aabb::AABB;
 use bvh::ray::Ray;
@@ -8,7 +9,7 @@ use crate::prelude::*;
 use crate::{effect, StringId, StringIdOptionCopy};
 use crate::attrs::{AttrCommand, AttrCommandQueue};
 use crate::effect::EffectCommand;
-use crate::game::GameState;
+use crate::sim::{BattleStage, BattleSystem};
 use crate::table::{TableData, TableDataItem};
 
 #[derive(Clone, Copy)]
@@ -26,7 +27,8 @@ pub enum Movement {
     Line(f32),
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, TypeUuid)]
+#[uuid = "e41a9c67-2f8d-4b5e-a3c0-7d9b1f6e2a58"]
 pub struct ForceConfig {
     pub name: StringId,
     pub select: HitTargetSelect,
@@ -53,10 +55,12 @@ impl TableDataItem for ForceConfig {
 
 #[derive(Component)]
 pub struct CreateForceReq {
-    id: u64,
+    pub id: u64,
+    //the tower that fired it, a monster the force kills counts for it
+    pub source: Option<Entity>,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy)]
 pub enum ForceTarget {
     Entity(Entity),
     Position(Vec3),
@@ -69,6 +73,7 @@ struct Force {
     pub fire_sfx: Option<u64>,
     pub hit_sfx: Option<u64>,
     pub effect: u64,
+    pub source: Option<Entity>,
 }
 
 #[derive(Component)]
@@ -83,15 +88,14 @@ pub struct ForcePlugin;
 
 impl Plugin for ForcePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_update(GameState::Playing)
-            .with_system(create_force_system)
-            .with_system(update_force_immediate.chain(effect::handle_effect_system)));
+        app.add_system_to_stage(BattleStage, create_force_system.after(BattleSystem::Abilities).before(BattleSystem::Forces))
+            .add_system_to_stage(BattleStage, update_force_immediate.chain(effect::handle_effect_system).label(BattleSystem::Forces).after(BattleSystem::Beams));
     }
 }
 
 fn create_force_system(
     mut commands: Commands,
-    query: Query<(Entity, &CreateForceReq, &ForceTarget, &Transform)>,
+    query: Query<(Entity, &CreateForceReq, &ForceTarget, &Transform), Added<CreateForceReq>>,
     table: Res<TableData<ForceConfig>>,
 ) {
     for (entity, req, target, transform) in query.iter() {
@@ -104,6 +108,7 @@ fn create_force_system(
                 fire_sfx: config.fire_sfx.as_id(),
                 self_sfx: config.self_sfx.as_id(),
                 effect: config.effect.id(),
+                source: req.source,
             }
         );
 
@@ -126,11 +131,17 @@ fn update_force_immediate(mut commands: Commands,
     let bvh = hit_query.deref();
     let mut cmds = Vec::new();
     for (entity, force, target, mut transform) in query.iter_mut() {
+        //an immediate force hits in the tick it arrives and is gone after it
+        commands.entity(entity).despawn_recursive();
+
         let transform = transform.deref_mut();
         let target_pos = match target {
             ForceTarget::Entity(e) => {
-                let p = global_transform_query.get(*e).expect(format!("failed to find global transform for entity {:?}", e).as_str());
-                p.translation
+                //the target died or leaked before the force arrived
+                match global_transform_query.get(*e) {
+                    Ok(p) => p.translation,
+                    Err(_) => continue,
+                }
             }
             ForceTarget::Position(p) => {
                 *p
@@ -140,11 +151,18 @@ fn update_force_immediate(mut commands: Commands,
         let start_pos = transform.translation;
         transform.translation = target_pos;
 
-        let target_as_result = HitResult::create_with_entity(entity);
+        let target_as_result = match target {
+            ForceTarget::Entity(e) => {
+                Some(HitResult::create_with_entity(*e))
+            }
+            ForceTarget::Position(_) => {
+                None
+            }
+        };
 
         let result_list = match force.select {
             HitTargetSelect::Target => {
-                vec![&target_as_result]
+                target_as_result.iter().collect()
             }
 
             HitTargetSelect::Circle(radius) => {
@@ -161,7 +179,7 @@ fn update_force_immediate(mut commands: Commands,
         };
 
         cmds.extend(result_list.into_iter().map(|hr| {
-            EffectCommand { target: hr.entity, id: force.effect }
+            EffectCommand { target: hr.entity, id: force.effect, source: force.source }
         }));
     }
 
@@ -1,59 +1,117 @@
 ﻿use bevy::app::Plugin;
 use bevy::prelude::*;
-use std::ops::Deref;
-use bevy::tasks::AsyncComputeTaskPool;
-use crate::{monster, stage};
-use crate::attacker::AttackerConfig;
+use bevy::reflect::TypeUuid;
+use bevy::asset::AssetPlugin;
+use bevy::transform::TransformPlugin;
+use bevy::transform::transform_propagate_system::transform_propagate_system;
+use crate::{attacker, input, monster, placement, sim, stage, validate};
+use crate::attacker::{AttackerConfig, AttackerPlugin};
 use crate::attrs::AttrPlugin;
+use crate::battle::BattlePlugin;
 use crate::camera::LookTransformPlugin;
+use crate::difficulty::DifficultyConfig;
 use crate::effect::EffectsConfig;
+use crate::endless::EndlessConfig;
 use crate::force::{ForceConfig, ForcePlugin};
 use crate::hit_query::HitQueryPlugin;
+use crate::input::PlayerInputs;
 use crate::map::{MapConfigAsset, MapConfigAssetLoader};
+use crate::placement::TowerBrush;
+use crate::proto::PathEditor::MapConfig;
 use crate::monster::MonsterConfig;
 use crate::prelude::App;
-use crate::table::{TableData, TableDataItem};
+use crate::stage::{AllWavesFinished, WaveFinished, WaveQueueStarted, WaveSpawnedUnit, WaveStarted};
+use crate::sim::{BattleClock, BattleRng, BattleStage, BattleSystem, BATTLE_STEP};
+use crate::validate::{ValidateScenes, ValidationReport};
+use crate::view::ViewPlugin;
+use crate::table::{install_table_system, TableAssetLoader, TableData, TableDataItem, TableError, TableHandles, TableReloaded};
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
     Loading,
     Playing,
-    #[allow(dead_code)]
     Result,
 }
 
-pub struct GamePlugin {}
+pub struct GamePlugin {
+    //no window, rendering or scenes, the battle advances one tick per update, e.g. for CI or a server
+    pub headless: bool,
+}
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         #[cfg(feature = "debug")]
             {
                 hashtoollib::load_reverse_dict("assets/config/hash.json");
+                hashtoollib::set_strict(true);
             }
 
-        app.insert_resource(
-            WindowDescriptor {
-                title: "Rich".to_string(),
-                width: 960.,
-                height: 540.,
-                vsync: true,
-                ..Default::default()
-            })
-            .add_plugins(DefaultPlugins)
-            .add_plugin(LookTransformPlugin)
-            .add_plugin(AttrPlugin)
+        if self.headless {
+            app.add_plugins(MinimalPlugins)
+                .add_plugin(TransformPlugin::default())
+                .add_plugin(AssetPlugin::default());
+
+            //a clock inserted before the plugin, e.g. with another step, is kept
+            if !app.world.contains_resource::<BattleClock>() {
+                app.insert_resource(BattleClock::fixed(BATTLE_STEP));
+            }
+        } else {
+            app.insert_resource(
+                WindowDescriptor {
+                    title: "Rich".to_string(),
+                    width: 960.,
+                    height: 540.,
+                    vsync: true,
+                    ..Default::default()
+                })
+                .insert_resource(BattleClock::real_time(BATTLE_STEP))
+                .add_plugins(DefaultPlugins)
+                .add_plugin(LookTransformPlugin)
+                .add_plugin(ViewPlugin)
+                .init_resource::<TowerBrush>()
+                .insert_resource(ValidateScenes)
+                .add_system_set(SystemSet::on_update(GameState::Playing).with_system(placement::pick_build_slot_system))
+                .add_system(bevy::input::system::exit_on_esc_system);
+        }
+
+        //a seed inserted before the plugin, e.g. by a test or a replay, is kept
+        if !app.world.contains_resource::<BattleRng>() {
+            let seed = rand::random();
+            info!("battle seed {}", seed);
+            app.insert_resource(BattleRng::new(seed));
+        }
+
+        //transforms are propagated first, so every tick sees the GlobalTransform of the last one
+        app.init_resource::<PlayerInputs>()
+            .add_stage_after(CoreStage::Update, BattleStage, SystemStage::single_threaded()
+                .with_run_criteria(sim::battle_tick_criteria)
+                .with_system(transform_propagate_system.label(BattleSystem::Propagate))
+                .with_system(input::apply_player_input_system.label(BattleSystem::Input).after(BattleSystem::Propagate))
+                .with_system(attacker::spawn_attacker_system.label(BattleSystem::SpawnAttackers).after(BattleSystem::Input)));
+
+        app.add_plugin(AttrPlugin)
             .add_plugin(HitQueryPlugin)
+            .add_plugin(AttackerPlugin)
             .add_plugin(ForcePlugin)
+            .add_plugin(BattlePlugin)
 
             .add_state(GameState::Loading)
             .add_system_set(SystemSet::on_enter(GameState::Loading).with_system(start_load))
-            .add_system_set(SystemSet::on_update(GameState::Loading).with_system(check_load_finish))
-
-            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(stage::init_stage_system))
-            .add_system_set(SystemSet::on_update(GameState::Playing)
-                .with_system(stage::update_stage_system)
-                .with_system(monster::move_by_map_path_system))
-            .add_system(bevy::input::system::exit_on_esc_system)
+            .add_system_set(SystemSet::on_update(GameState::Loading)
+                .with_system(validate::validate_battle_system.exclusive_system().at_start())
+                .with_system(check_load_finish))
+
+            .add_system_set(SystemSet::on_enter(GameState::Playing)
+                .with_system(stage::init_stage_system))
+            .add_event::<WaveQueueStarted>()
+            .add_event::<WaveStarted>()
+            .add_event::<WaveSpawnedUnit>()
+            .add_event::<WaveFinished>()
+            .add_event::<AllWavesFinished>()
+            .add_system_to_stage(BattleStage, stage::update_stage_system.label(BattleSystem::Stage).after(BattleSystem::SpawnAttackers))
+            .add_system_to_stage(BattleStage, stage::update_wave_preview_system.after(BattleSystem::Stage).before(BattleSystem::Move))
+            .add_system_to_stage(BattleStage, monster::move_by_map_path_system.label(BattleSystem::Move).after(BattleSystem::Stage))
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(monster::refresh_monster_system))
             .init_asset_loader::<MapConfigAssetLoader>()
             .add_asset::<MapConfigAsset>();
 
@@ -61,129 +119,100 @@ impl Plugin for GamePlugin {
 
         #[cfg(feature = "debug")]
             {
-                app.add_plugin(bevy_prototype_debug_lines::DebugLinesPlugin::default());
+                app.world.get_resource::<AssetServer>().unwrap().watch_for_changes().expect("failed to watch asset changes");
+                if !self.headless {
+                    app.add_plugin(bevy_prototype_debug_lines::DebugLinesPlugin::default());
+                }
                 //app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(stage::draw_stage_roads));
             }
     }
 }
 
+pub const MAP_PATH: &str = "config/map/Map_pb.map";
+pub const ATTACKER_TABLE: &str = "config/ron/battle.attacker.ron";
+pub const MONSTER_TABLE: &str = "config/ron/battle.monster.ron";
+pub const EFFECT_TABLE: &str = "config/ron/battle.effect.ron";
+pub const FORCE_TABLE: &str = "config/ron/battle.force.ron";
+pub const ENDLESS_TABLE: &str = "config/ron/battle.endless.ron";
+pub const DIFFICULTY_TABLE: &str = "config/ron/battle.difficulty.ron";
+
 pub fn load_battle_tables(app: &mut App) {
-    load_table::<AttackerConfig>(app, "assets/config/ron/attacker.ron");
-    load_table::<MonsterConfig>(app, "assets/config/ron/monster.ron");
-    load_table::<EffectsConfig>(app, "assets/config/ron/effect.ron");
-    load_table::<ForceConfig>(app, "assets/config/ron/force.ron");
+    app.init_resource::<TableHandles>();
+    load_table::<AttackerConfig>(app, ATTACKER_TABLE);
+    load_table::<MonsterConfig>(app, MONSTER_TABLE);
+    load_table::<EffectsConfig>(app, EFFECT_TABLE);
+    load_table::<ForceConfig>(app, FORCE_TABLE);
+    load_table::<EndlessConfig>(app, ENDLESS_TABLE);
+    load_table::<DifficultyConfig>(app, DIFFICULTY_TABLE);
 }
 
-fn load_table<T>(app: &mut App, path: &str) where T: TableDataItem {
-    app.insert_resource(TableData::<T>::load_from_file(path));
+fn load_table<T>(app: &mut App, path: &'static str) where T: TableDataItem + TypeUuid {
+    let mut handles = app.world.get_resource_mut::<TableHandles>().unwrap();
+    handles.register(path);
+    let loader = TableAssetLoader::<T>::create(path, &handles);
+    app.add_asset::<TableData<T>>()
+        .add_asset_loader(loader)
+        .add_event::<TableReloaded<T>>()
+        .add_system_to_stage(CoreStage::PreUpdate, install_table_system::<T>);
 }
 
-fn start_load(mut commands: Commands, asset_server: Res<AssetServer>, thread_pool: Res<AsyncComputeTaskPool>) {
-    let handle: Handle<MapConfigAsset> = asset_server.load("config/map/Map_pb.map");
-    commands.insert_resource(handle);
-
-    commands.spawn_bundle((
-        Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, 0.0f32, 180.0f32.to_radians(), 0.0f32)),
-        GlobalTransform::identity()
-    )).with_children(|parent| {
-        parent.spawn_scene(asset_server.load("gltf/Map_export.glb#Scene0"));
-    });
+//the battle tables loaded synchronously, for tools running without an AssetServer
+pub struct BattleTables {
+    pub attacker: TableData<AttackerConfig>,
+    pub monster: TableData<MonsterConfig>,
+    pub effect: TableData<EffectsConfig>,
+    pub force: TableData<ForceConfig>,
+    pub endless: TableData<EndlessConfig>,
+    pub difficulty: TableData<DifficultyConfig>,
+    asset_root: String,
 }
 
-fn check_load_finish(mut commands: Commands, map: Res<Assets<MapConfigAsset>>,
-                     mut state: ResMut<State<GameState>>,
-                     map_handle: Res<Handle<MapConfigAsset>>,
-                     mut meshes: ResMut<Assets<Mesh>>,
-                     mut materials: ResMut<Assets<StandardMaterial>>) {
-    if let Some(asset) = map.get(map_handle.deref()) {
-        let config = &asset.config;
-
-        //camera
-        {
-            let c = config.camera.as_ref().unwrap();
-            let pos = c.position.as_ref().unwrap();
-
-            let rot = c.rotation.as_ref().unwrap();
-            let transform = Transform::from_matrix(
-                Mat4::from_scale_rotation_translation(Vec3::ONE,
-                                                      Quat::from_xyzw(rot.x, rot.y, rot.z, rot.w),
-                                                      Vec3::new(pos.x, pos.y, pos.z)));
-
-            commands.spawn_bundle(PerspectiveCameraBundle {
-                transform,
-                ..Default::default()
-            });
-
-            //let look_at = c.look_at.as_ref().unwrap();
-            // commands.spawn_bundle(OrbitCameraBundle::new(
-            //     OrbitCameraController::default(),
-            //     PerspectiveCameraBundle::default(),
-            //     Vec3::new(pos.x, pos.y, pos.z),
-            //     Vec3::new(look_at.x, look_at.y, look_at.z),
-            // ));
+impl BattleTables {
+    pub fn load_from_dir(asset_root: &str) -> Result<Self, TableError> {
+        fn load<T>(asset_root: &str, path: &str) -> Result<TableData<T>, TableError> where T: TableDataItem {
+            TableData::<T>::try_load_from_file(&format!("{}/{}", asset_root, path))
         }
 
-        //light
-        //if false
-        {
-            const HALF_SIZE: f32 = 1.0;
-            let light_config = config.light.as_ref().unwrap();
-            let color = light_config.color.as_ref().unwrap();
-            let pos = light_config.position.as_ref().unwrap();
-            // let look_at = light_config.look_at.as_ref().unwrap();
-            // let transform = Transform::from_xyz(pos.x, pos.y, pos.z).looking_at(Vec3::new(look_at.x, look_at.y, look_at.z), Vec3::Y);
-            let rot = light_config.rotation.as_ref().unwrap();
-            let transform = Transform::from_matrix(
-                Mat4::from_scale_rotation_translation(Vec3::ONE,
-                                                      Quat::from_xyzw(rot.x, rot.y, rot.z, rot.w),
-                                                      Vec3::new(pos.x, pos.y, pos.z)));
-
-            commands.spawn_bundle(DirectionalLightBundle {
-                directional_light: DirectionalLight {
-                    shadow_projection: OrthographicProjection {
-                        left: -HALF_SIZE,
-                        right: HALF_SIZE,
-                        bottom: -HALF_SIZE,
-                        top: HALF_SIZE,
-                        near: -10.0 * HALF_SIZE,
-                        far: 10.0 * HALF_SIZE,
-                        ..Default::default()
-                    },
-                    shadow_depth_bias: light_config.shadow_bias,
-                    shadow_normal_bias: light_config.shadow_normal_bias,
-                    shadows_enabled: true,
-                    color: Color::rgb(color.x, color.y, color.z),
-                    ..Default::default()
-                },
-                transform,
-                ..Default::default()
-            });
-        }
+        Ok(BattleTables {
+            attacker: load(asset_root, ATTACKER_TABLE)?,
+            monster: load(asset_root, MONSTER_TABLE)?,
+            effect: load(asset_root, EFFECT_TABLE)?,
+            force: load(asset_root, FORCE_TABLE)?,
+            endless: load(asset_root, ENDLESS_TABLE)?,
+            difficulty: load(asset_root, DIFFICULTY_TABLE)?,
+            asset_root: asset_root.to_string(),
+        })
+    }
 
-        //path point
-        {
-            let ps = &config.paths;
-
-            let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
-            let material = materials.add(StandardMaterial {
-                base_color: Color::GREEN,
-                ..Default::default()
-            });
-
-            for path in ps {
-                for point in &path.points {
-                    let pos = point.position.as_ref().unwrap();
-
-                    commands.spawn_bundle(PbrBundle {
-                        mesh: mesh.clone(),
-                        material: material.clone(),
-                        transform: Transform::from_xyz(pos.x, pos.y + 0.5f32, pos.z),
-                        ..Default::default()
-                    });
-                }
-            }
+    pub fn save_baked(&self) -> Result<(), TableError> {
+        self.attacker.save_baked()?;
+        self.monster.save_baked()?;
+        self.effect.save_baked()?;
+        self.force.save_baked()?;
+        self.endless.save_baked()?;
+        self.difficulty.save_baked()
+    }
+
+    pub fn validate(&self, map: Option<(&str, &MapConfig)>) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        report.validate_tables(&self.attacker, &self.monster, &self.effect, &self.force, &self.endless, &self.difficulty);
+        report.validate_scenes(&self.attacker, |asset| std::fs::read(format!("{}/{}", self.asset_root, asset)).ok());
+        if let Some((file, config)) = map {
+            report.validate_map(file, config, &self.monster, &self.endless);
         }
+        report
+    }
+}
+
+fn start_load(mut commands: Commands, asset_server: Res<AssetServer>, mut tables: ResMut<TableHandles>) {
+    let handle: Handle<MapConfigAsset> = asset_server.load(MAP_PATH);
+    commands.insert_resource(handle);
+    tables.start_load(&asset_server);
+}
 
+fn check_load_finish(mut state: ResMut<State<GameState>>,
+                     report: Option<Res<ValidationReport>>) {
+    if matches!(report, Some(report) if report.is_ok()) {
         state.set(GameState::Playing).expect(format!("failed to switch game state to {:?}", GameState::Playing).as_str());
     }
 }
@@ -1,13 +1,11 @@
-use bevy::tasks::{AsyncComputeTaskPool, Task};
-use futures_lite::future;
 use itertools::Itertools;
 use bvh::aabb::{AABB, Bounded};
 use bvh::bounding_hierarchy::BHShape;
 use bvh::bvh::BVH;
 use bvh::ray::Ray;
 use bvh::Vector3;
-use crate::game::GameState;
 use crate::prelude::*;
+use crate::sim::{BattleStage, BattleSystem};
 
 #[derive(Component)]
 pub struct HitBounds {
@@ -22,19 +20,35 @@ impl HitBounds {
 
 pub struct HitQuery {
     bvh: Option<BvhBundle>,
-    task: Option<Task<BvhBundle>>,
 }
 
 impl HitQuery {
+    //a tree without targets has no root node to start from
     pub fn traverse(&self, ray: &Ray) -> Vec<&HitResult> {
         let b = self.bvh.as_ref().expect("the bvh not build");
+        if b.targets.is_empty() {
+            return Vec::new();
+        }
         b.bvh.traverse(ray, &b.targets)
     }
 
     pub fn traverse_aabb(&self, aabb: &AABB) -> Vec<&HitResult> {
         let b = self.bvh.as_ref().expect("the bvh not build");
+        if b.targets.is_empty() {
+            return Vec::new();
+        }
         b.bvh.traverse_aabb(aabb, &b.targets)
     }
+
+    //every bounds touching the sphere, in entity order so the result does not depend on the tree layout
+    pub fn overlap_sphere(&self, center: Vec3, radius: f32) -> Vec<&HitResult> {
+        let half_size = Vector3::new(radius, radius, radius);
+        let mut results = self.traverse_aabb(&AABB::with_bounds(center - half_size, center + half_size)).into_iter()
+            .filter(|hr| hr.position.distance(center) <= radius + hr.radius)
+            .collect_vec();
+        results.sort_by_key(|hr| hr.entity);
+        results
+    }
 }
 
 struct BvhBundle {
@@ -50,6 +64,10 @@ pub struct HitResult {
 }
 
 impl HitResult {
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
     pub fn create_with_entity(entity: Entity) -> Self {
         HitResult {
             entity,
@@ -87,25 +105,16 @@ impl Plugin for HitQueryPlugin {
         app.insert_resource(
             HitQuery {
                 bvh: None,
-                task: None,
             }
         )
 
-            //change to pre update remove after bug:https://github.com/bevyengine/bevy/issues/1671 fix
-            .add_system_set_to_stage(CoreStage::Update,
-                                     SystemSet::on_update(GameState::Playing).
-                                         with_system(prepare_bvh_tree_system));
-        // .add_system_set_to_stage(CoreStage::PostUpdate,
-        //                          SystemSet::on_update(GameState::Playing).
-        //                              with_system(build_bvh_tree_system));
+            //rebuilt every tick before the forces query it, a tree finished later on a task would make the battle depend on thread timing
+            .add_system_to_stage(BattleStage, prepare_bvh_tree_system.label(BattleSystem::HitQuery).after(BattleSystem::Move));
     }
 }
 
 
-fn prepare_bvh_tree_system(
-    query: Query<(Entity, &GlobalTransform, &HitBounds)>, mut worker: ResMut<HitQuery>,
-    thread_pool: Res<AsyncComputeTaskPool>,
-) {
+fn prepare_bvh_tree_system(query: Query<(Entity, &GlobalTransform, &HitBounds)>, mut worker: ResMut<HitQuery>) {
     let mut nodes = query.iter().enumerate().
         map(|(idx, (entity, t, hit))| {
             HitResult {
@@ -116,20 +125,5 @@ fn prepare_bvh_tree_system(
             }
         }).collect_vec();
 
-    if worker.bvh.is_none() {
-        worker.bvh = Some(BvhBundle { bvh: BVH::build(&mut nodes), targets: nodes });
-    } else {
-        let task = thread_pool.spawn(async move {
-            BvhBundle { bvh: BVH::build(&mut nodes), targets: nodes }
-        });
-
-        worker.task = Some(task);
-    }
-}
-
-fn build_bvh_tree_system(mut worker: ResMut<HitQuery>) {
-    if let Some(task) = &mut worker.task {
-        let bvh = future::block_on(task);
-        worker.bvh = Some(bvh);
-    }
+    worker.bvh = Some(BvhBundle { bvh: BVH::build(&mut nodes), targets: nodes });
 }
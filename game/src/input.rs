@@ -0,0 +1,157 @@
+use std::ops::DerefMut;
+use serde::{Deserialize, Serialize};
+use crate::attacker::{upgrade_attacker, Attacker, AttackerConfig, ChannelAbilityConfig, CreateAttackerReq, ShootAbilityConfig, TargetPriority, UpgradeAbilities};
+use crate::battle::BattleBase;
+use crate::placement::{place_tower, sell_tower, upgrade_tower, BuildSlots, PlacementError};
+use crate::prelude::*;
+use crate::replay::{ReplayPlayback, ReplayRecorder};
+use crate::sim::BattleClock;
+use crate::stage::MapStage;
+use crate::table::TableData;
+
+//everything a player can do to the battle, it only takes effect at the start of the next tick so a replay can repeat it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PlayerInput {
+    //builds an attacker on a free build slot if there is gold for it
+    PlaceTower { attacker: u64, slot: usize },
+    //switches what the tower on a build slot aims at
+    SetTargetPriority { slot: usize, priority: TargetPriority },
+    //turns the tower on a build slot into one of the upgrades of its entry if there is gold for it
+    UpgradeTower { slot: usize, to: u64 },
+    //takes the tower away for a part of what it cost
+    SellTower { slot: usize },
+    //starts the next wave of a started queue now for its early call reward
+    CallWave { queue: usize },
+}
+
+//inputs gathered from the window, e.g. mouse picking, waiting for the next tick
+#[derive(Default)]
+pub struct PlayerInputs {
+    pending: Vec<PlayerInput>,
+}
+
+impl PlayerInputs {
+    pub fn push(&mut self, input: PlayerInput) {
+        self.pending.push(input);
+    }
+}
+
+type TowerQuery<'w, 's> = Query<'w, 's, (Entity, &'static mut Attacker, &'static mut TargetPriority, Option<&'static Children>)>;
+
+//the tower on a build slot, a tower sold earlier in the tick is still there until its commands run
+fn tower_on(towers: &mut TowerQuery, slot: usize, sold: &[Entity]) -> Option<Entity> {
+    towers.iter_mut().find(|(entity, attacker, ..)| attacker.slot == slot && !sold.contains(entity)).map(|(entity, ..)| entity)
+}
+
+pub fn apply_player_input_system(mut commands: Commands,
+                                 mut inputs: ResMut<PlayerInputs>,
+                                 clock: Res<BattleClock>,
+                                 mut map_stage: ResMut<MapStage>,
+                                 mut base: ResMut<BattleBase>,
+                                 mut slots: ResMut<BuildSlots>,
+                                 attacker_table: Res<TableData<AttackerConfig>>,
+                                 mut towers: TowerQuery,
+                                 abilities: Query<(), Or<(With<ShootAbilityConfig>, With<ChannelAbilityConfig>)>>,
+                                 recorder: Option<ResMut<ReplayRecorder>>,
+                                 playback: Option<ResMut<ReplayPlayback>>) {
+    let inputs = match playback {
+        //a played back battle ignores the live inputs
+        Some(mut playback) => {
+            inputs.pending.clear();
+            playback.take_inputs(clock.tick())
+        }
+        None => {
+            std::mem::take(&mut inputs.deref_mut().pending)
+        }
+    };
+
+    if let Some(mut recorder) = recorder {
+        recorder.record_inputs(clock.tick(), &inputs);
+    }
+
+    let (mut sold, mut upgraded) = (Vec::new(), Vec::new());
+    for input in inputs {
+        match input {
+            PlayerInput::PlaceTower { attacker, slot } => {
+                match place_tower(&mut slots, &mut base, &attacker_table, slot, attacker) {
+                    Ok(position) => {
+                        commands.spawn_bundle((
+                            Transform::from_translation(position),
+                            GlobalTransform::identity(),
+                            CreateAttackerReq { id: attacker, slot, spent: attacker_table.index(attacker).cost, abilities: vec![attacker] },
+                        ));
+                    }
+                    Err(e) => {
+                        warn!("failed to place tower: {}", e);
+                    }
+                }
+            }
+            PlayerInput::SetTargetPriority { slot, priority } => {
+                match tower_on(&mut towers, slot, &sold).and_then(|entity| towers.get_mut(entity).ok()) {
+                    Some((_, _, mut current, _)) => {
+                        *current = priority;
+                    }
+                    None => {
+                        warn!("build slot {} has no tower to aim", slot);
+                    }
+                }
+            }
+            PlayerInput::UpgradeTower { slot, to } => {
+                let (entity, mut attacker, _, children) = match tower_on(&mut towers, slot, &sold).and_then(|entity| towers.get_mut(entity).ok()) {
+                    Some(tower) => tower,
+                    None => {
+                        warn!("failed to upgrade tower: {}", PlacementError::NoTower(slot));
+                        continue;
+                    }
+                };
+                //the abilities of the first upgrade are only spawned at the end of the tick, a second one could not replace them
+                if upgraded.contains(&entity) {
+                    warn!("the tower on build slot {} was already upgraded in this tick", slot);
+                    continue;
+                }
+                let from = attacker.id;
+                match upgrade_tower(&mut attacker, &mut base, &attacker_table, to) {
+                    Ok(mode) => {
+                        let replaced = match mode {
+                            UpgradeAbilities::Replace => {
+                                children.iter().flat_map(|children| children.iter()).copied().filter(|child| abilities.get(*child).is_ok()).collect()
+                            }
+                            UpgradeAbilities::Add => {
+                                Vec::new()
+                            }
+                        };
+                        upgrade_attacker(&mut commands, entity, attacker_table.index(from), attacker_table.index(to), &replaced);
+                        upgraded.push(entity);
+                    }
+                    Err(e) => {
+                        warn!("failed to upgrade tower: {}", e);
+                    }
+                }
+            }
+            PlayerInput::SellTower { slot } => {
+                match tower_on(&mut towers, slot, &sold).and_then(|entity| towers.get_mut(entity).ok()) {
+                    Some((entity, attacker, ..)) => {
+                        let refund = sell_tower(&attacker, &mut slots, &mut base, &attacker_table);
+                        info!("tower on build slot {} sold for {} gold", slot, refund);
+                        commands.entity(entity).despawn_recursive();
+                        sold.push(entity);
+                    }
+                    None => {
+                        warn!("failed to sell tower: {}", PlacementError::NoTower(slot));
+                    }
+                }
+            }
+            PlayerInput::CallWave { queue } => {
+                match map_stage.call_next_wave(queue) {
+                    Some(call) => {
+                        base.gold += call.reward;
+                        info!("wave {} of queue {} called {:.1}s early for {} gold", call.wave, queue, call.seconds, call.reward);
+                    }
+                    None => {
+                        warn!("queue {} has no wave to call", queue);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -8,6 +8,7 @@ mod stage;
 mod attacker;
 mod monster;
 mod table;
+mod table_inherit;
 mod node;
 mod force;
 mod sfx;
@@ -17,27 +18,80 @@ mod death;
 pub mod str_gen;
 mod destroy;
 mod effect;
+mod validate;
+mod sim;
+mod input;
+mod replay;
+mod battle;
+mod view;
+mod endless;
+mod difficulty;
+mod save;
+mod placement;
 
 pub mod prelude {
     pub use bevy::prelude::*;
-    pub use crate::{StringId, StringIdOptionCopy, str_gen};
+    pub use crate::{StringId, StringIdOptionCopy, str_gen, sid};
 }
 
 pub use game::GamePlugin;
-pub use game::load_battle_tables;
+pub use sim::{battle_state_hash, BattleClock, BattleRng, BattleStage, BATTLE_STEP};
+pub use input::{PlayerInput, PlayerInputs};
+pub use stage::{AllWavesFinished, EarlyWaveCall, MapStage, UpcomingWave, WaveFinished, WavePreview, WaveQueueStarted, WaveSpawnedUnit, WaveStarted};
+pub use battle::{BattleBase, BattleOutcome, BattleSummary, DEFAULT_GOLD, DEFAULT_LIVES};
+pub use placement::{place_tower, sell_tower, upgrade_tower, BuildSlots, PlacementError, TowerBrush};
+pub use attacker::{TargetPriority, UpgradeAbilities};
+pub use endless::{EndlessConfig, EndlessMode};
+pub use difficulty::{DifficultyConfig, DifficultyMode};
+pub use replay::{save_replay, Replay, ReplayMode, ReplayPlayback, ReplayPlugin};
+pub use save::{save_battle, BattleSave, SaveMode, SavePlugin};
+pub use game::{load_battle_tables, BattleTables, MAP_PATH};
+pub use map::load_map_config;
+pub use proto::PathEditor::MapConfig;
+pub use validate::ValidationReport;
+pub use table::TableError;
 
 use rand::Rng;
 use crate::prelude::Vec3;
 
-pub fn rand_position(pos: &Vec3, range: f32) -> Vec3 {
-    let mut rng = rand::thread_rng();
+pub fn rand_position(pos: &Vec3, range: f32, rng: &mut BattleRng) -> Vec3 {
     let x = pos.x + rng.gen_range(0f32..range);
     let y = pos.y + rng.gen_range(0f32..range);
     let z = pos.z + rng.gen_range(0f32..range);
     Vec3::new(x, y, z)
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Clone)]
+//readable name of a hashed id for logs, only the debug build keeps the reverse dictionary
+pub fn id_to_name(id: u64) -> String {
+    #[cfg(feature = "debug")]
+        {
+            if let Some(s) = hashtoollib::un_hash(id) {
+                return s;
+            }
+        }
+    id.to_string()
+}
+
+//id of a config name hashed at compile time, e.g. sid!("cross_bullet"), debug builds also register it for un_hash
+//const items can use hashtoollib::const_hash directly, they can not register the name
+#[macro_export]
+macro_rules! sid {
+    ($name:literal) => {{
+        const ID: u64 = $crate::hashtoollib::const_hash($name);
+        if $crate::REGISTER_SID {
+            static REGISTERED: std::sync::Once = std::sync::Once::new();
+            REGISTERED.call_once(|| $crate::hashtoollib::register(ID, $name));
+        }
+        ID
+    }};
+}
+
+#[doc(hidden)]
+pub use hashtoollib;
+#[doc(hidden)]
+pub const REGISTER_SID: bool = cfg!(feature = "debug");
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
 pub enum StringId {
     Str(String),
     Id(u64),
@@ -56,24 +110,42 @@ impl StringId {
         }
     }
 
-    pub fn id(&self) -> u64 {
+    pub fn try_id(&self) -> Result<u64, TableError> {
         match self {
             StringId::Str(s) => {
-                panic!("the string {} id not convert into id yet", s.as_str());
+                Err(TableError::UnconvertedStringId { value: s.clone() })
             }
             StringId::Id(id) => {
-                *id
+                Ok(*id)
             }
         }
     }
 
-    pub fn str(&self) -> &str {
+    pub fn id(&self) -> u64 {
+        match self.try_id() {
+            Ok(id) => id,
+            Err(e) => {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    pub fn try_str(&self) -> Result<&str, TableError> {
         match self {
             StringId::Str(s) => {
-                s.as_str()
+                Ok(s.as_str())
             }
             StringId::Id(id) => {
-                panic!("the string has not convert into id yet");
+                Err(TableError::StringIdNotStr { id: *id })
+            }
+        }
+    }
+
+    pub fn str(&self) -> &str {
+        match self.try_str() {
+            Ok(s) => s,
+            Err(e) => {
+                panic!("{}", e);
             }
         }
     }
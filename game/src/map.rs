@@ -10,6 +10,12 @@ use super::proto::PathEditor::*;
 #[uuid = "dcb91f31-722c-40e3-862c-eaa05e872436"]
 pub struct MapConfigAsset {
     pub config: MapConfig,
+    pub source_hash: u64,
+}
+
+pub fn load_map_config(bytes: &[u8]) -> anyhow::Result<MapConfig> {
+    let mut reader = BytesReader::from_bytes(bytes);
+    MapConfig::from_reader(&mut reader, bytes).map_err(|e| anyhow::anyhow!("Cannot read MapConfig: {}", e))
 }
 
 #[derive(Default)]
@@ -18,9 +24,8 @@ pub struct MapConfigAssetLoader;
 impl AssetLoader for MapConfigAssetLoader {
     fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let mut reader = BytesReader::from_bytes(bytes);
-            let config = MapConfig::from_reader(&mut reader, bytes).expect("Cannot read MapConfig");
-            let asset = MapConfigAsset { config };
+            let config = load_map_config(bytes)?;
+            let asset = MapConfigAsset { config, source_hash: hashtoollib::hash_bytes(bytes) };
             load_context.set_default_asset(LoadedAsset::new(asset));
             Ok(())
         })
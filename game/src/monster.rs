@@ -4,17 +4,28 @@ use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
 use bevy::tasks::ComputeTaskPool;
 use serde::{Serialize, Deserialize};
-use crate::attrs::AttrsConfig;
+use crate::attrs::{Attrs, AttrsConfig};
+use crate::difficulty::DifficultyConfig;
+use crate::hit_query::HitBounds;
+use crate::sim::BattleClock;
 use crate::stage::MapStage;
-use crate::table::TableDataItem;
+use crate::table::{TableData, TableDataItem, TableReloaded};
+use crate::view::SceneView;
 
 #[derive(Deserialize, Serialize, TypeUuid)]
-#[uuid = "9a852db2-3eb7-4c91-99ae-ec1ea92f2877"]
+#[uuid = "3c0e5f3a-8d5b-4f0e-9a57-0c6d2b1e7f41"]
 pub struct MonsterConfig {
     pub name: String,
     pub move_speed: f32,
     pub asset: String,
     pub attrs: AttrsConfig,
+    //lives the base loses when this monster reaches the end of its road
+    #[serde(default = "default_leak_damage")]
+    pub leak_damage: u32,
+}
+
+fn default_leak_damage() -> u32 {
+    1
 }
 
 impl TableDataItem for MonsterConfig {
@@ -27,6 +38,11 @@ impl TableDataItem for MonsterConfig {
     }
 }
 
+#[derive(Component)]
+pub struct Monster {
+    pub id: u64,
+}
+
 #[derive(Component, Debug)]
 pub struct MoveWithMapPath {
     pub road_index: usize,
@@ -37,20 +53,32 @@ pub struct MoveWithMapPath {
 #[derive(Component)]
 pub struct MoveWithPathEnded {}
 
-pub fn move_by_map_path_system(commands: Commands,
+//every component a monster walking a road is made of, for the waves and for a resumed battle
+pub fn monster_bundle(id: u64, config: &MonsterConfig, transform: Transform, move_with: MoveWithMapPath, attrs: Attrs) -> impl Bundle {
+    (
+        transform,
+        GlobalTransform::identity(),
+        move_with,
+        HitBounds::create(0.2f32),
+        attrs,
+        Monster { id },
+        SceneView { asset: config.asset.clone() },
+    )
+}
+
+pub fn move_by_map_path_system(mut commands: Commands,
                                pool: Res<ComputeTaskPool>,
                                mut query: Query<(Entity, &mut MoveWithMapPath, &mut Transform), Without<MoveWithPathEnded>>,
                                stage: Res<MapStage>,
-                               time: Res<Time>) {
-    let delta = time.delta_seconds();
+                               clock: Res<BattleClock>) {
+    let delta = clock.delta_seconds();
     let stage = stage.deref();
-    let cs = Mutex::new(commands);
+    let ended = Mutex::new(Vec::new());
 
     query.par_for_each_mut(&pool, 64, |(entity, mut move_with, mut transform)| {
         let road = &stage.roads[move_with.road_index];
         if move_with.target_point_index > road.points.len() - 1 {
-            let mut g = cs.lock().unwrap();
-            g.entity(entity).insert(MoveWithPathEnded {});
+            ended.lock().unwrap().push(entity);
             return;
         }
 
@@ -64,4 +92,27 @@ pub fn move_by_map_path_system(commands: Commands,
             move_with.target_point_index += 1;
         }
     });
-}
\ No newline at end of file
+
+    //the parallel workers finish in any order, insert in entity order to keep the archetype rows the same every run
+    let mut ended = ended.into_inner().unwrap();
+    ended.sort();
+    for entity in ended {
+        commands.entity(entity).insert(MoveWithPathEnded {});
+    }
+}
+
+pub fn refresh_monster_system(mut reloaded: EventReader<TableReloaded<MonsterConfig>>,
+                              table: Res<TableData<MonsterConfig>>,
+                              difficulty: Res<DifficultyConfig>,
+                              mut query: Query<(&Monster, &mut MoveWithMapPath, &mut Attrs)>) {
+    if reloaded.iter().last().is_none() {
+        return;
+    }
+
+    for (monster, mut move_with, mut attrs) in query.iter_mut() {
+        if let Some(config) = table.find(monster.id) {
+            move_with.speed = config.move_speed * difficulty.move_speed;
+            attrs.refresh_from_config(&config.attrs);
+        }
+    }
+}
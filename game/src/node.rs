@@ -36,6 +36,7 @@ fn collect_names(map: &mut HashMap<u64, Entity>,
 
 //attacker system relay on the GLTF node name to find fire points, sfx attach
 pub fn init_node_name_system(
+    mut commands: Commands,
     mut hierarchy_query: Query<(Entity, &mut HierarchyNameMap), (Without<HierarchyNameMapInitTag>, With<Children>)>,
     children_query: Query<&Children>,
     name_query: Query<&Name>,
@@ -43,6 +44,10 @@ pub fn init_node_name_system(
     for (entity, mut hierarchy) in hierarchy_query.iter_mut() {
         let  map = &mut hierarchy.values;
         collect_names(map, entity, &children_query, &name_query);
+        //the scene shows up some frames after the entity, look again until its nodes are there
+        if !map.is_empty() {
+            commands.entity(entity).insert(HierarchyNameMapInitTag {});
+        }
     }
 }
 
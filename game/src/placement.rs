@@ -0,0 +1,360 @@
+use std::fmt::{Display, Formatter};
+use crate::attacker::{Attacker, AttackerConfig, UpgradeAbilities};
+use crate::battle::BattleBase;
+use crate::input::{PlayerInput, PlayerInputs};
+use crate::prelude::*;
+use crate::proto::PathEditor::MapConfig;
+use crate::stage::MapStageRoad;
+use crate::table::TableData;
+
+//a map without authored slots gets one every BUILD_GRID_SPACING, unless it is closer than ROAD_CLEARANCE to a road
+const BUILD_GRID_SPACING: f32 = 2f32;
+const ROAD_CLEARANCE: f32 = 1.5f32;
+//how far from the mouse ray a slot can be picked
+const PICK_RADIUS: f32 = 1f32;
+
+//where towers can be built, a slot holds at most one tower
+pub struct BuildSlots {
+    positions: Vec<Vec3>,
+    occupied: Vec<bool>,
+}
+
+impl BuildSlots {
+    pub fn create(config: &MapConfig, roads: &[MapStageRoad]) -> Self {
+        let positions = match config.build_slots.is_empty() {
+            true => {
+                Self::grid_outside_roads(roads)
+            }
+            false => {
+                config.build_slots.iter().map(|p| Vec3::new(p.x, p.y, p.z)).collect::<Vec<_>>()
+            }
+        };
+
+        BuildSlots { occupied: vec![false; positions.len()], positions }
+    }
+
+    fn grid_outside_roads(roads: &[MapStageRoad]) -> Vec<Vec3> {
+        let points = roads.iter().flat_map(|road| road.points.iter().map(|p| p.pos)).collect::<Vec<_>>();
+        if points.is_empty() {
+            return Vec::new();
+        }
+
+        let segments = roads.iter().flat_map(|road| road.points.windows(2).map(|w| (w[0].pos, w[1].pos))).collect::<Vec<_>>();
+        //on the ground, x and z of the world go to x and y
+        let distance_to_road = |p: Vec2| segments.iter().map(|(a, b)| {
+            let (a, b) = (Vec2::new(a.x, a.z), Vec2::new(b.x, b.z));
+            let ab = b - a;
+            let t = ((p - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0f32, 1f32);
+            (a + ab * t).distance(p)
+        }).fold(f32::MAX, f32::min);
+
+        //the grid is aligned to the origin, so a slot stays where it is when a road moves a little
+        let min = points.iter().fold(Vec3::splat(f32::MAX), |m, p| m.min(*p)) - Vec3::splat(BUILD_GRID_SPACING);
+        let max = points.iter().fold(Vec3::splat(f32::MIN), |m, p| m.max(*p)) + Vec3::splat(BUILD_GRID_SPACING);
+        let (x0, x1) = ((min.x / BUILD_GRID_SPACING).ceil() as i32, (max.x / BUILD_GRID_SPACING).floor() as i32);
+        let (z0, z1) = ((min.z / BUILD_GRID_SPACING).ceil() as i32, (max.z / BUILD_GRID_SPACING).floor() as i32);
+
+        let mut slots = Vec::new();
+        for z in z0..=z1 {
+            for x in x0..=x1 {
+                let (px, pz) = (x as f32 * BUILD_GRID_SPACING, z as f32 * BUILD_GRID_SPACING);
+                if distance_to_road(Vec2::new(px, pz)) < ROAD_CLEARANCE {
+                    continue;
+                }
+                //the height of the nearest road point
+                let y = points.iter().min_by(|a, b| {
+                    let da = Vec2::new(a.x - px, a.z - pz).length_squared();
+                    let db = Vec2::new(b.x - px, b.z - pz).length_squared();
+                    da.total_cmp(&db)
+                }).unwrap().y;
+                slots.push(Vec3::new(px, y, pz));
+            }
+        }
+        slots
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn position(&self, slot: usize) -> Option<Vec3> {
+        self.positions.get(slot).copied()
+    }
+
+    pub fn is_free(&self, slot: usize) -> bool {
+        self.occupied.get(slot) == Some(&false)
+    }
+
+    pub(crate) fn set_occupied(&mut self, slot: usize, occupied: bool) {
+        self.occupied[slot] = occupied;
+    }
+
+    //the slot closest to the ray, dir has to be normalized
+    pub fn pick(&self, origin: Vec3, dir: Vec3) -> Option<usize> {
+        self.positions.iter().enumerate().filter_map(|(slot, p)| {
+            let along = (*p - origin).dot(dir);
+            let distance = (*p - (origin + dir * along.max(0f32))).length();
+            (distance <= PICK_RADIUS).then_some((slot, distance))
+        }).min_by(|a, b| a.1.total_cmp(&b.1)).map(|(slot, _)| slot)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlacementError {
+    NoSlot(usize),
+    Occupied(usize),
+    UnknownAttacker(u64),
+    NotEnoughGold { cost: u32, gold: u32 },
+    NoTower(usize),
+    NoUpgrade { from: u64, to: u64 },
+}
+
+impl Display for PlacementError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlacementError::NoSlot(slot) => {
+                write!(f, "there is no build slot {}", slot)
+            }
+            PlacementError::Occupied(slot) => {
+                write!(f, "build slot {} already has a tower", slot)
+            }
+            PlacementError::UnknownAttacker(id) => {
+                write!(f, "no attacker '{}' to build", crate::id_to_name(*id))
+            }
+            PlacementError::NotEnoughGold { cost, gold } => {
+                write!(f, "the tower costs {} gold but there is only {}", cost, gold)
+            }
+            PlacementError::NoTower(slot) => {
+                write!(f, "build slot {} has no tower", slot)
+            }
+            PlacementError::NoUpgrade { from, to } => {
+                write!(f, "'{}' can not be upgraded to '{}'", crate::id_to_name(*from), crate::id_to_name(*to))
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlacementError {}
+
+//checks the slot, pays for the tower and takes the slot, returns where the tower goes
+pub fn place_tower(slots: &mut BuildSlots,
+                   base: &mut BattleBase,
+                   table: &TableData<AttackerConfig>,
+                   slot: usize,
+                   attacker: u64) -> Result<Vec3, PlacementError> {
+    let position = slots.position(slot).ok_or(PlacementError::NoSlot(slot))?;
+    if !slots.is_free(slot) {
+        return Err(PlacementError::Occupied(slot));
+    }
+    let config = table.find(attacker).ok_or(PlacementError::UnknownAttacker(attacker))?;
+    if base.gold < config.cost {
+        return Err(PlacementError::NotEnoughGold { cost: config.cost, gold: base.gold });
+    }
+
+    base.gold -= config.cost;
+    slots.set_occupied(slot, true);
+    Ok(position)
+}
+
+//checks the upgrade is in the tree of the tower and pays for it, the tower is the new entry afterwards
+pub fn upgrade_tower(attacker: &mut Attacker,
+                     base: &mut BattleBase,
+                     table: &TableData<AttackerConfig>,
+                     to: u64) -> Result<UpgradeAbilities, PlacementError> {
+    let config = table.find(attacker.id).ok_or(PlacementError::UnknownAttacker(attacker.id))?;
+    let upgrade = config.upgrades.iter().find(|upgrade| upgrade.to.id() == to).ok_or(PlacementError::NoUpgrade { from: attacker.id, to })?;
+    table.find(to).ok_or(PlacementError::UnknownAttacker(to))?;
+    if base.gold < upgrade.cost {
+        return Err(PlacementError::NotEnoughGold { cost: upgrade.cost, gold: base.gold });
+    }
+
+    base.gold -= upgrade.cost;
+    attacker.spent += upgrade.cost;
+    attacker.id = to;
+    match upgrade.abilities {
+        UpgradeAbilities::Replace => {
+            attacker.abilities = vec![to];
+        }
+        UpgradeAbilities::Add => {
+            attacker.abilities.push(to);
+        }
+    }
+    Ok(upgrade.abilities)
+}
+
+//frees the slot and gives back the sell refund of the tower's entry, returns the refunded gold
+pub fn sell_tower(attacker: &Attacker,
+                  slots: &mut BuildSlots,
+                  base: &mut BattleBase,
+                  table: &TableData<AttackerConfig>) -> u32 {
+    let refund = table.find(attacker.id).map_or(0, |config| (attacker.spent as f32 * config.sell_refund.clamp(0f32, 1f32)) as u32);
+    base.gold += refund;
+    slots.set_occupied(attacker.slot, false);
+    refund
+}
+
+//the attacker a click builds, the first one by name until something picks another
+#[derive(Default)]
+pub struct TowerBrush {
+    pub attacker: Option<u64>,
+}
+
+//left click on a build slot asks for a tower there, the placement itself is checked in the next tick
+pub fn pick_build_slot_system(mouse: Res<Input<MouseButton>>,
+                              windows: Res<Windows>,
+                              cameras: Query<(&Camera, &GlobalTransform)>,
+                              slots: Res<BuildSlots>,
+                              brush: Res<TowerBrush>,
+                              table: Res<TableData<AttackerConfig>>,
+                              mut inputs: ResMut<PlayerInputs>) {
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (cursor, (camera, transform)) = match (window.cursor_position(), cameras.iter().next()) {
+        (Some(cursor), Some(camera)) => (cursor, camera),
+        _ => return,
+    };
+
+    //the projection is reversed z, the near plane is at 1 and z goes to 0 far away
+    let ndc = cursor / Vec2::new(window.width(), window.height()) * 2f32 - Vec2::ONE;
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    let near = ndc_to_world.project_point3(ndc.extend(1f32));
+    let far = ndc_to_world.project_point3(ndc.extend(0.001f32));
+
+    let slot = match slots.pick(near, (far - near).normalize()) {
+        Some(slot) => slot,
+        None => return,
+    };
+    let attacker = match brush.attacker {
+        Some(attacker) => attacker,
+        None => {
+            match table.iter().min_by_key(|(_, config)| config.name.as_str()) {
+                Some((id, _)) => id,
+                None => return,
+            }
+        }
+    };
+    inputs.push(PlayerInput::PlaceTower { attacker, slot });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::PathEditor::MapVector3;
+    use crate::stage::MapStageRoadPoint;
+
+    fn road(points: &[(f32, f32)]) -> MapStageRoad {
+        MapStageRoad { points: points.iter().map(|(x, z)| MapStageRoadPoint { pos: Vec3::new(*x, 0f32, *z) }).collect() }
+    }
+
+    fn attackers() -> TableData<AttackerConfig> {
+        TableData::try_load_from_bytes(br#"[(name: "main", asset: "", cost: 50, abilities: [])]"#, "attackers").unwrap()
+    }
+
+    #[test]
+    fn grid_keeps_clear_of_roads() {
+        let roads = vec![road(&[(0f32, 0f32), (10f32, 0f32)])];
+        let slots = BuildSlots::create(&MapConfig::default(), &roads);
+
+        assert!(!slots.is_empty());
+        for slot in 0..slots.len() {
+            let p = slots.position(slot).unwrap();
+            assert!(p.z.abs() >= ROAD_CLEARANCE || p.x < -ROAD_CLEARANCE || p.x > 10f32 + ROAD_CLEARANCE, "slot {} at {} is on the road", slot, p);
+        }
+    }
+
+    #[test]
+    fn authored_slots_replace_the_grid() {
+        let config = MapConfig {
+            build_slots: vec![MapVector3 { x: 1f32, y: 2f32, z: 3f32 }],
+            ..Default::default()
+        };
+        let slots = BuildSlots::create(&config, &[road(&[(0f32, 0f32), (10f32, 0f32)])]);
+
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots.position(0), Some(Vec3::new(1f32, 2f32, 3f32)));
+        assert_eq!(slots.pick(Vec3::new(1f32, 10f32, 3.5f32), -Vec3::Y), Some(0));
+        assert_eq!(slots.pick(Vec3::new(5f32, 10f32, 3f32), -Vec3::Y), None);
+    }
+
+    #[test]
+    fn placing_spends_gold_and_takes_the_slot() {
+        let config = MapConfig {
+            build_slots: vec![MapVector3 { x: 0f32, y: 0f32, z: 0f32 }, MapVector3 { x: 4f32, y: 0f32, z: 0f32 }],
+            ..Default::default()
+        };
+        let mut slots = BuildSlots::create(&config, &[]);
+        let mut base = BattleBase { lives: 1, gold: 70 };
+        let table = attackers();
+        let main = hashtoollib::hash("main");
+
+        assert_eq!(place_tower(&mut slots, &mut base, &table, 2, main), Err(PlacementError::NoSlot(2)));
+        assert_eq!(place_tower(&mut slots, &mut base, &table, 0, 1), Err(PlacementError::UnknownAttacker(1)));
+        assert_eq!(place_tower(&mut slots, &mut base, &table, 0, main), Ok(Vec3::ZERO));
+        assert_eq!(base.gold, 20);
+        assert_eq!(place_tower(&mut slots, &mut base, &table, 0, main), Err(PlacementError::Occupied(0)));
+        assert_eq!(place_tower(&mut slots, &mut base, &table, 1, main), Err(PlacementError::NotEnoughGold { cost: 50, gold: 20 }));
+        assert!(slots.is_free(1));
+    }
+
+    fn upgrade_tree() -> TableData<AttackerConfig> {
+        TableData::try_load_from_bytes(br#"[
+            (name: "main", asset: "", cost: 50, abilities: [], upgrades: [(to: Str("rapid"), cost: 40), (to: Str("beam"), cost: 60, abilities: Add)], sell_refund: 0.6),
+            (name: "rapid", asset: "", cost: 90, abilities: []),
+            (name: "beam", asset: "", cost: 110, abilities: [])
+        ]"#, "attackers").unwrap()
+    }
+
+    #[test]
+    fn upgrades_follow_the_tree_and_keep_the_tower() {
+        let table = upgrade_tree();
+        let (main, rapid, beam) = (hashtoollib::hash("main"), hashtoollib::hash("rapid"), hashtoollib::hash("beam"));
+        let mut base = BattleBase { lives: 1, gold: 100 };
+        let mut attacker = Attacker { id: main, slot: 0, kills: 3, spent: 50, abilities: vec![main] };
+
+        assert_eq!(upgrade_tower(&mut attacker, &mut base, &table, 1), Err(PlacementError::NoUpgrade { from: main, to: 1 }));
+        assert_eq!(upgrade_tower(&mut attacker, &mut base, &table, beam), Ok(UpgradeAbilities::Add));
+        assert_eq!((attacker.id, attacker.slot, attacker.kills, attacker.spent), (beam, 0, 3, 110));
+        assert_eq!(attacker.abilities, vec![main, beam]);
+        assert_eq!(base.gold, 40);
+
+        //beam is the end of its branch
+        assert_eq!(upgrade_tower(&mut attacker, &mut base, &table, rapid), Err(PlacementError::NoUpgrade { from: beam, to: rapid }));
+
+        let mut attacker = Attacker { id: main, slot: 0, kills: 0, spent: 50, abilities: vec![main] };
+        assert_eq!(upgrade_tower(&mut attacker, &mut base, &table, rapid), Ok(UpgradeAbilities::Replace));
+        assert_eq!(attacker.abilities, vec![rapid]);
+        assert_eq!(upgrade_tower(&mut Attacker { id: main, slot: 0, kills: 0, spent: 50, abilities: vec![main] }, &mut base, &table, rapid),
+                   Err(PlacementError::NotEnoughGold { cost: 40, gold: 0 }));
+    }
+
+    #[test]
+    fn selling_refunds_a_part_of_everything_spent() {
+        let table = upgrade_tree();
+        let config = MapConfig {
+            build_slots: vec![MapVector3 { x: 0f32, y: 0f32, z: 0f32 }],
+            ..Default::default()
+        };
+        let mut slots = BuildSlots::create(&config, &[]);
+        let mut base = BattleBase { lives: 1, gold: 0 };
+        slots.set_occupied(0, true);
+
+        let main = Attacker { id: hashtoollib::hash("main"), slot: 0, kills: 0, spent: 115, abilities: vec![] };
+        assert_eq!(sell_tower(&main, &mut slots, &mut base, &table), 69);
+        assert_eq!(base.gold, 69);
+        assert!(slots.is_free(0));
+
+        //an entry without its own refund gives back half
+        let rapid = Attacker { id: hashtoollib::hash("rapid"), slot: 0, kills: 0, spent: 90, abilities: vec![] };
+        assert_eq!(sell_tower(&rapid, &mut slots, &mut base, &table), 45);
+    }
+}
@@ -117,6 +117,7 @@ pub struct MapConfig {
     pub light: Option<PathEditor::LightConfig>,
     pub wave_queues: Vec<PathEditor::WaveQueue>,
     pub paths: Vec<PathEditor::PathData>,
+    pub build_slots: Vec<PathEditor::MapVector3>,
 }
 
 impl<'a> MessageRead<'a> for MapConfig {
@@ -128,6 +129,7 @@ impl<'a> MessageRead<'a> for MapConfig {
                 Ok(18) => msg.light = Some(r.read_message::<PathEditor::LightConfig>(bytes)?),
                 Ok(26) => msg.wave_queues.push(r.read_message::<PathEditor::WaveQueue>(bytes)?),
                 Ok(34) => msg.paths.push(r.read_message::<PathEditor::PathData>(bytes)?),
+                Ok(42) => msg.build_slots.push(r.read_message::<PathEditor::MapVector3>(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -143,6 +145,7 @@ impl MessageWrite for MapConfig {
         + self.light.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
         + self.wave_queues.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
         + self.paths.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.build_slots.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
     }
 
     fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
@@ -150,6 +153,7 @@ impl MessageWrite for MapConfig {
         if let Some(ref s) = self.light { w.write_with_tag(18, |w| w.write_message(s))?; }
         for s in &self.wave_queues { w.write_with_tag(26, |w| w.write_message(s))?; }
         for s in &self.paths { w.write_with_tag(34, |w| w.write_message(s))?; }
+        for s in &self.build_slots { w.write_with_tag(42, |w| w.write_message(s))?; }
         Ok(())
     }
 }
@@ -310,6 +314,7 @@ pub struct Wave {
     pub duration: f32,
     pub per_spawn_count: i32,
     pub path_index: i32,
+    pub early_call_reward: f32,
 }
 
 impl<'a> MessageRead<'a> for Wave {
@@ -323,6 +328,7 @@ impl<'a> MessageRead<'a> for Wave {
                 Ok(37) => msg.duration = r.read_float(bytes)?,
                 Ok(40) => msg.per_spawn_count = r.read_int32(bytes)?,
                 Ok(48) => msg.path_index = r.read_int32(bytes)?,
+                Ok(61) => msg.early_call_reward = r.read_float(bytes)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -340,6 +346,7 @@ impl MessageWrite for Wave {
         + if self.duration == 0f32 { 0 } else { 1 + 4 }
         + if self.per_spawn_count == 0i32 { 0 } else { 1 + sizeof_varint(*(&self.per_spawn_count) as u64) }
         + if self.path_index == 0i32 { 0 } else { 1 + sizeof_varint(*(&self.path_index) as u64) }
+        + if self.early_call_reward == 0f32 { 0 } else { 1 + 4 }
     }
 
     fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
@@ -349,14 +356,51 @@ impl MessageWrite for Wave {
         if self.duration != 0f32 { w.write_with_tag(37, |w| w.write_float(*&self.duration))?; }
         if self.per_spawn_count != 0i32 { w.write_with_tag(40, |w| w.write_int32(*&self.per_spawn_count))?; }
         if self.path_index != 0i32 { w.write_with_tag(48, |w| w.write_int32(*&self.path_index))?; }
+        if self.early_call_reward != 0f32 { w.write_with_tag(61, |w| w.write_float(*&self.early_call_reward))?; }
         Ok(())
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WaveTiming {
+    Absolute = 0,
+    RelativeToQueue = 1,
+    RelativeToPreviousWave = 2,
+}
+
+impl Default for WaveTiming {
+    fn default() -> Self {
+        WaveTiming::Absolute
+    }
+}
+
+impl From<i32> for WaveTiming {
+    fn from(i: i32) -> Self {
+        match i {
+            0 => WaveTiming::Absolute,
+            1 => WaveTiming::RelativeToQueue,
+            2 => WaveTiming::RelativeToPreviousWave,
+            _ => Self::default(),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for WaveTiming {
+    fn from(s: &'a str) -> Self {
+        match s {
+            "Absolute" => WaveTiming::Absolute,
+            "RelativeToQueue" => WaveTiming::RelativeToQueue,
+            "RelativeToPreviousWave" => WaveTiming::RelativeToPreviousWave,
+            _ => Self::default(),
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct WaveQueue {
     pub wait_time: f32,
     pub waves: Vec<PathEditor::Wave>,
+    pub timing: PathEditor::WaveTiming,
 }
 
 impl<'a> MessageRead<'a> for WaveQueue {
@@ -366,6 +410,7 @@ impl<'a> MessageRead<'a> for WaveQueue {
             match r.next_tag(bytes) {
                 Ok(13) => msg.wait_time = r.read_float(bytes)?,
                 Ok(18) => msg.waves.push(r.read_message::<PathEditor::Wave>(bytes)?),
+                Ok(24) => msg.timing = r.read_enum(bytes)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -379,11 +424,13 @@ impl MessageWrite for WaveQueue {
         0
         + if self.wait_time == 0f32 { 0 } else { 1 + 4 }
         + self.waves.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + if self.timing == PathEditor::WaveTiming::Absolute { 0 } else { 1 + sizeof_varint(*(&self.timing) as u64) }
     }
 
     fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
         if self.wait_time != 0f32 { w.write_with_tag(13, |w| w.write_float(*&self.wait_time))?; }
         for s in &self.waves { w.write_with_tag(18, |w| w.write_message(s))?; }
+        if self.timing != PathEditor::WaveTiming::Absolute { w.write_with_tag(24, |w| w.write_enum(*&self.timing as i32))?; }
         Ok(())
     }
 }
@@ -0,0 +1,225 @@
+use anyhow::Context;
+use bevy::app::{AppExit, Events};
+use serde::{Deserialize, Serialize};
+use crate::attacker::AttackerConfig;
+use crate::difficulty::{DifficultyConfig, DifficultyMode};
+use crate::effect::EffectsConfig;
+use crate::endless::{EndlessConfig, EndlessMode};
+use crate::force::ForceConfig;
+use crate::game::{GameState, ATTACKER_TABLE, DIFFICULTY_TABLE, EFFECT_TABLE, ENDLESS_TABLE, FORCE_TABLE, MAP_PATH, MONSTER_TABLE};
+use crate::input::PlayerInput;
+use crate::map::MapConfigAsset;
+use crate::monster::MonsterConfig;
+use crate::prelude::*;
+use crate::sim::{battle_state_hash, BattleClock, BattleRng, BattleStage};
+use crate::table::{TableData, TableDataItem};
+
+//bump when the replay layout, the meaning of an input or the state hash changes
+const REPLAY_VERSION: u32 = 7;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub step: f32,
+    //name of the EndlessConfig an endless battle was played with
+    #[serde(default)]
+    pub endless: Option<String>,
+    //name of the DifficultyConfig, none plays the tables as they are
+    #[serde(default)]
+    pub difficulty: Option<String>,
+    //path and source hash of the map and every table the battle was played with
+    pub content: Vec<(String, u64)>,
+    //(tick, input) in tick order
+    pub inputs: Vec<(u64, PlayerInput)>,
+    pub final_tick: u64,
+    pub final_state_hash: u64,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).context(format!("[{}]", path))?;
+        let replay: Replay = ron::de::from_str(&text).context(format!("[{}]", path))?;
+        if replay.version != REPLAY_VERSION {
+            anyhow::bail!("[{}] replay version {} can not be played by version {}", path, replay.version, REPLAY_VERSION);
+        }
+        //the battle runs in steps of this many seconds, none would never advance
+        if !replay.step.is_finite() || replay.step <= 0f32 {
+            anyhow::bail!("[{}] step {} has to be above 0", path, replay.step);
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, Default::default())?;
+        std::fs::write(path, text).context(format!("[{}]", path))
+    }
+}
+
+pub(crate) fn battle_content(world: &World) -> Vec<(String, u64)> {
+    fn table<T>(world: &World, path: &str) -> (String, u64) where T: TableDataItem {
+        let table = world.get_resource::<TableData<T>>().expect("tables not loaded");
+        (path.to_string(), table.source_hash())
+    }
+
+    let map_assets = world.get_resource::<Assets<MapConfigAsset>>().unwrap();
+    let map_handle = world.get_resource::<Handle<MapConfigAsset>>().unwrap();
+    let map = map_assets.get(map_handle).expect("no map config loaded");
+    vec![
+        (MAP_PATH.to_string(), map.source_hash),
+        table::<AttackerConfig>(world, ATTACKER_TABLE),
+        table::<MonsterConfig>(world, MONSTER_TABLE),
+        table::<EffectsConfig>(world, EFFECT_TABLE),
+        table::<ForceConfig>(world, FORCE_TABLE),
+        table::<EndlessConfig>(world, ENDLESS_TABLE),
+        table::<DifficultyConfig>(world, DIFFICULTY_TABLE),
+    ]
+}
+
+pub struct ReplayRecorder {
+    path: String,
+    replay: Replay,
+}
+
+impl ReplayRecorder {
+    pub(crate) fn record_inputs(&mut self, tick: u64, inputs: &[PlayerInput]) {
+        self.replay.inputs.extend(inputs.iter().map(|input| (tick, input.clone())));
+    }
+}
+
+pub struct ReplayPlayback {
+    replay: Replay,
+    next_input: usize,
+    finished: bool,
+}
+
+impl ReplayPlayback {
+    pub(crate) fn take_inputs(&mut self, tick: u64) -> Vec<PlayerInput> {
+        let mut inputs = Vec::new();
+        while let Some((input_tick, input)) = self.replay.inputs.get(self.next_input) {
+            if *input_tick > tick {
+                break;
+            }
+            inputs.push(input.clone());
+            self.next_input += 1;
+        }
+        inputs
+    }
+
+    //true once the final tick was reached and its state hash matched
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+pub enum ReplayMode {
+    Record(String),
+    Play(String),
+}
+
+//added after GamePlugin, playing a replay takes over the seed and the step of the battle
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.mode {
+            ReplayMode::Record(path) => {
+                app.insert_resource(ReplayRecorder { path: path.clone(), replay: Default::default() })
+                    .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(start_record_system.exclusive_system().at_end()))
+                    .add_system_to_stage(CoreStage::Last, save_replay_on_exit_system.exclusive_system());
+            }
+            ReplayMode::Play(path) => {
+                let replay = match Replay::load(path) {
+                    Ok(replay) => replay,
+                    Err(e) => {
+                        panic!("failed to load replay: {:?}", e);
+                    }
+                };
+
+                app.insert_resource(BattleRng::new(replay.seed));
+                match &replay.endless {
+                    Some(name) => {
+                        app.insert_resource(EndlessMode { name: name.clone() });
+                    }
+                    None => {
+                        app.world.remove_resource::<EndlessMode>();
+                    }
+                }
+                match &replay.difficulty {
+                    Some(name) => {
+                        app.insert_resource(DifficultyMode { name: name.clone() });
+                    }
+                    None => {
+                        app.world.remove_resource::<DifficultyMode>();
+                    }
+                }
+                app.world.get_resource_mut::<BattleClock>().expect("ReplayPlugin must be added after GamePlugin").set_step(replay.step);
+                app.insert_resource(ReplayPlayback { replay, next_input: 0, finished: false })
+                    .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(check_content_system.exclusive_system().at_end()))
+                    .add_system_to_stage(BattleStage, check_playback_system.exclusive_system().at_end());
+            }
+        }
+    }
+}
+
+fn start_record_system(world: &mut World) {
+    let content = battle_content(world);
+    let seed = world.get_resource::<BattleRng>().unwrap().seed();
+    let step = world.get_resource::<BattleClock>().unwrap().delta_seconds();
+    let endless = world.get_resource::<EndlessMode>().map(|mode| mode.name.clone());
+    let difficulty = world.get_resource::<DifficultyMode>().map(|mode| mode.name.clone());
+
+    let mut recorder = world.get_resource_mut::<ReplayRecorder>().unwrap();
+    recorder.replay = Replay { version: REPLAY_VERSION, seed, step, endless, difficulty, content, ..Default::default() };
+}
+
+//writes the recorded battle, done by the recorder on exit, a headless run can call it whenever it stops
+pub fn save_replay(world: &mut World) -> anyhow::Result<()> {
+    let state_hash = battle_state_hash(world);
+    let tick = world.get_resource::<BattleClock>().unwrap().tick();
+
+    let mut recorder = world.get_resource_mut::<ReplayRecorder>().context("no replay is recorded")?;
+    recorder.replay.final_tick = tick;
+    recorder.replay.final_state_hash = state_hash;
+    recorder.replay.save(&recorder.path)?;
+    info!("replay saved to {}, {} ticks", recorder.path, tick);
+    Ok(())
+}
+
+fn save_replay_on_exit_system(world: &mut World) {
+    let exit = world.get_resource::<Events<AppExit>>().map_or(false, |e| e.iter_current_update_events().next().is_some());
+    if exit {
+        if let Err(e) = save_replay(world) {
+            error!("failed to save replay: {:?}", e);
+        }
+    }
+}
+
+//a replay of other content still plays, but will most likely fail the final state check
+fn check_content_system(world: &mut World) {
+    let content = battle_content(world);
+    let playback = world.get_resource::<ReplayPlayback>().unwrap();
+    for (path, hash) in &playback.replay.content {
+        if !content.contains(&(path.clone(), *hash)) {
+            warn!("{} changed since the replay was recorded", path);
+        }
+    }
+}
+
+fn check_playback_system(world: &mut World) {
+    let tick = world.get_resource::<BattleClock>().unwrap().tick();
+    let playback = world.get_resource::<ReplayPlayback>().unwrap();
+    if playback.finished || tick < playback.replay.final_tick {
+        return;
+    }
+
+    let state_hash = battle_state_hash(world);
+    let mut playback = world.get_resource_mut::<ReplayPlayback>().unwrap();
+    if state_hash != playback.replay.final_state_hash {
+        panic!("replay diverged, state hash at tick {} is {:016x} instead of {:016x}", tick, state_hash, playback.replay.final_state_hash);
+    }
+    playback.finished = true;
+    info!("replay matched at tick {}", tick);
+}
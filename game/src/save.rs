@@ -0,0 +1,428 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::Context;
+use bevy::app::{AppExit, Events};
+use serde::{Deserialize, Serialize};
+use crate::attacker::{spawn_attacker_system, Attacker, AttackerConfig, ChannelAbilityRuntime, CreateAttackerReq, ShootAbilityRuntime, TargetPriority};
+use crate::attrs::Attrs;
+use crate::battle::{BattleBase, BattleSummary};
+use crate::difficulty::DifficultyMode;
+use crate::endless::EndlessMode;
+use crate::force::{CreateForceReq, ForceTarget};
+use crate::monster::{monster_bundle, Monster, MonsterConfig, MoveWithMapPath, MoveWithPathEnded};
+use crate::placement::BuildSlots;
+use crate::prelude::*;
+use crate::replay::battle_content;
+use crate::sim::{BattleClock, BattleRng, BattleStage};
+use crate::stage::{MapStage, MapStageProgress};
+use crate::table::TableData;
+
+//bump when the save layout or the meaning of a saved value changes
+const SAVE_VERSION: u32 = 7;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct TransformSave {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    scale: [f32; 3],
+}
+
+impl TransformSave {
+    fn create(transform: &Transform) -> Self {
+        TransformSave {
+            translation: transform.translation.to_array(),
+            rotation: Vec4::from(transform.rotation).to_array(),
+            scale: transform.scale.to_array(),
+        }
+    }
+
+    fn to_transform(self) -> Transform {
+        Transform {
+            translation: Vec3::from(self.translation),
+            rotation: Quat::from_vec4(Vec4::from(self.rotation)),
+            scale: Vec3::from(self.scale),
+        }
+    }
+}
+
+//entity is only used to point forces at their target again
+#[derive(Serialize, Deserialize)]
+struct MonsterSave {
+    entity: u64,
+    id: u64,
+    transform: TransformSave,
+    road_index: usize,
+    target_point_index: usize,
+    speed: f32,
+    attrs: Vec<(u64, f32)>,
+}
+
+//the target is a saved monster entity like the one of a force
+#[derive(Serialize, Deserialize)]
+struct ShootSave {
+    target: Option<u64>,
+    ammo: u32,
+    cool_down: f32,
+    reload: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelSave {
+    target: Option<u64>,
+    value: f32,
+    exhausted: bool,
+    charge: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TowerSave {
+    entity: u64,
+    id: u64,
+    slot: usize,
+    transform: TransformSave,
+    priority: TargetPriority,
+    kills: u32,
+    spent: u32,
+    abilities: Vec<u64>,
+    //one per shoot and channel ability, in the order of the config
+    shoot: Vec<ShootSave>,
+    channel: Vec<ChannelSave>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ForceTargetSave {
+    Entity(u64),
+    Position([f32; 3]),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ForceSave {
+    id: u64,
+    source: Option<u64>,
+    target: ForceTargetSave,
+    transform: TransformSave,
+}
+
+//a battle between two ticks, resuming it goes on with the next tick
+#[derive(Serialize, Deserialize)]
+pub struct BattleSave {
+    pub version: u32,
+    pub tick: u64,
+    pub step: f32,
+    pub seed: u64,
+    rng_word_pos: u64,
+    pub endless: Option<String>,
+    pub difficulty: Option<String>,
+    //path and source hash of the map and every table, like a replay
+    pub content: Vec<(String, u64)>,
+    stage: MapStageProgress,
+    lives: u32,
+    gold: u32,
+    kills: u32,
+    leaks: u32,
+    monsters: Vec<MonsterSave>,
+    towers: Vec<TowerSave>,
+    forces: Vec<ForceSave>,
+}
+
+impl BattleSave {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).context(format!("[{}]", path))?;
+        let save: BattleSave = ron::de::from_str(&text).context(format!("[{}]", path))?;
+        if save.version != SAVE_VERSION {
+            anyhow::bail!("[{}] save version {} can not be loaded by version {}", path, save.version, SAVE_VERSION);
+        }
+        //the battle runs in steps of this many seconds, none would never advance
+        if !save.step.is_finite() || save.step <= 0f32 {
+            anyhow::bail!("[{}] step {} has to be above 0", path, save.step);
+        }
+        Ok(save)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, Default::default())?;
+        std::fs::write(path, text).context(format!("[{}]", path))
+    }
+}
+
+//saves between two ticks, e.g. after App::update, the battle has to be playing
+pub fn save_battle(world: &mut World, path: &str) -> anyhow::Result<()> {
+    let stage = world.get_resource::<MapStage>().context("no battle is playing")?.progress();
+    let clock = world.get_resource::<BattleClock>().unwrap();
+    let (tick, step) = (clock.tick(), clock.delta_seconds());
+    let rng = world.get_resource::<BattleRng>().unwrap();
+    let (seed, rng_word_pos) = (rng.seed(), rng.word_pos());
+    let base = world.get_resource::<BattleBase>().unwrap();
+    let (lives, gold) = (base.lives, base.gold);
+    let summary = world.get_resource::<BattleSummary>().unwrap();
+    let (kills, leaks) = (summary.kills, summary.leaks);
+
+    let mut monsters = world.query_filtered::<(Entity, &Monster, &Transform, &MoveWithMapPath, &Attrs), Without<MoveWithPathEnded>>()
+        .iter(world)
+        .map(|(entity, monster, transform, move_with, attrs)| MonsterSave {
+            entity: entity.to_bits(),
+            id: monster.id,
+            transform: TransformSave::create(transform),
+            road_index: move_with.road_index,
+            target_point_index: move_with.target_point_index,
+            speed: move_with.speed,
+            attrs: attrs.sorted_values(),
+        }).collect::<Vec<_>>();
+    monsters.sort_by_key(|monster| monster.entity);
+
+    let mut towers = world.query::<(Entity, &Attacker, &TargetPriority, &Transform, Option<&Children>)>().iter(world)
+        .map(|(entity, attacker, priority, transform, children)| {
+            let children = children.map_or(&[][..], |children| &children[..]);
+            let shoot = children.iter().filter_map(|child| world.get::<ShootAbilityRuntime>(*child)).map(|runtime| ShootSave {
+                target: runtime.target.map(|e| e.to_bits()),
+                ammo: runtime.ammo,
+                cool_down: runtime.cool_down,
+                reload: runtime.reload,
+            }).collect();
+            let channel = children.iter().filter_map(|child| world.get::<ChannelAbilityRuntime>(*child)).map(|runtime| ChannelSave {
+                target: runtime.target.map(|e| e.to_bits()),
+                value: runtime.value,
+                exhausted: runtime.exhausted,
+                charge: runtime.charge,
+            }).collect();
+            (entity, TowerSave {
+                entity: entity.to_bits(),
+                id: attacker.id,
+                slot: attacker.slot,
+                transform: TransformSave::create(transform),
+                priority: priority.clone(),
+                kills: attacker.kills,
+                spent: attacker.spent,
+                abilities: attacker.abilities.clone(),
+                shoot,
+                channel,
+            })
+        }).collect::<Vec<_>>();
+    towers.sort_by_key(|(entity, _)| *entity);
+
+    let mut forces = world.query::<(Entity, &CreateForceReq, &ForceTarget, &Transform)>().iter(world)
+        .map(|(entity, req, target, transform)| {
+            let target = match target {
+                ForceTarget::Entity(e) => {
+                    ForceTargetSave::Entity(e.to_bits())
+                }
+                ForceTarget::Position(p) => {
+                    ForceTargetSave::Position(p.to_array())
+                }
+            };
+            (entity, ForceSave { id: req.id, source: req.source.map(|e| e.to_bits()), target, transform: TransformSave::create(transform) })
+        }).collect::<Vec<_>>();
+    forces.sort_by_key(|(entity, _)| *entity);
+
+    let save = BattleSave {
+        version: SAVE_VERSION,
+        tick,
+        step,
+        seed,
+        rng_word_pos,
+        endless: world.get_resource::<EndlessMode>().map(|mode| mode.name.clone()),
+        difficulty: world.get_resource::<DifficultyMode>().map(|mode| mode.name.clone()),
+        content: battle_content(world),
+        stage,
+        lives,
+        gold,
+        kills,
+        leaks,
+        monsters,
+        towers: towers.into_iter().map(|(_, tower)| tower).collect(),
+        forces: forces.into_iter().map(|(_, force)| force).collect(),
+    };
+    save.save(path)?;
+    info!("battle saved to {} at tick {}", path, tick);
+    Ok(())
+}
+
+pub enum SaveMode {
+    //writes the battle when the app exits while playing
+    SaveOnExit(String),
+    Resume(String),
+}
+
+//added after GamePlugin, resuming takes over the seed, the step, the endless mode and the difficulty of the save
+pub struct SavePlugin {
+    pub mode: SaveMode,
+}
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        match &self.mode {
+            SaveMode::SaveOnExit(path) => {
+                let path = path.clone();
+                app.add_system_to_stage(CoreStage::Last, (move |world: &mut World| save_battle_on_exit(world, &path)).exclusive_system());
+            }
+            SaveMode::Resume(path) => {
+                let save = match BattleSave::load(path) {
+                    Ok(save) => save,
+                    Err(e) => {
+                        panic!("failed to load battle save: {:?}", e);
+                    }
+                };
+
+                app.insert_resource(BattleRng::new(save.seed));
+                app.world.get_resource_mut::<BattleClock>().expect("SavePlugin must be added after GamePlugin").set_step(save.step);
+                match &save.endless {
+                    Some(name) => {
+                        app.insert_resource(EndlessMode { name: name.clone() });
+                    }
+                    None => {
+                        app.world.remove_resource::<EndlessMode>();
+                    }
+                }
+                match &save.difficulty {
+                    Some(name) => {
+                        app.insert_resource(DifficultyMode { name: name.clone() });
+                    }
+                    None => {
+                        app.world.remove_resource::<DifficultyMode>();
+                    }
+                }
+                app.insert_resource(PendingResume(save))
+                    .add_system_to_stage(BattleStage, resume_battle_system.exclusive_system().at_start());
+            }
+        }
+    }
+}
+
+fn save_battle_on_exit(world: &mut World, path: &str) {
+    let exit = world.get_resource::<Events<AppExit>>().map_or(false, |e| e.iter_current_update_events().next().is_some());
+    if exit && world.get_resource::<MapStage>().is_some() {
+        if let Err(e) = save_battle(world, path) {
+            error!("failed to save battle: {:?}", e);
+        }
+    }
+}
+
+struct PendingResume(BattleSave);
+
+//runs before anything else of the first tick, the battle set up by entering Playing is replaced by the save
+fn resume_battle_system(world: &mut World) {
+    let save = match world.remove_resource::<PendingResume>() {
+        Some(PendingResume(save)) => save,
+        None => return,
+    };
+
+    if let Err(e) = resume_battle(world, &save) {
+        error!("failed to resume battle, it starts from the beginning: {:?}", e);
+    }
+}
+
+//the save is checked against the map and the tables before anything is replaced, a broken one leaves the battle untouched
+fn check_resume(world: &World, save: &BattleSave) -> anyhow::Result<()> {
+    let slots = world.get_resource::<BuildSlots>().unwrap();
+    let attackers = world.get_resource::<TableData<AttackerConfig>>().unwrap();
+    let monsters = world.get_resource::<TableData<MonsterConfig>>().unwrap();
+    let mut taken = HashSet::new();
+    for tower in &save.towers {
+        if tower.slot >= slots.len() {
+            anyhow::bail!("tower on slot {} but the map has {} slots", tower.slot, slots.len());
+        }
+        if !slots.is_free(tower.slot) || !taken.insert(tower.slot) {
+            anyhow::bail!("slot {} is taken by more than one tower", tower.slot);
+        }
+        if let Some(id) = tower.abilities.iter().chain([&tower.id]).find(|id| attackers.find(**id).is_none()) {
+            anyhow::bail!("tower on slot {} uses '{}' which is not in {}", tower.slot, crate::id_to_name(*id), attackers.path());
+        }
+    }
+    if let Some(monster) = save.monsters.iter().find(|monster| monsters.find(monster.id).is_none()) {
+        anyhow::bail!("monster '{}' is not in {}", crate::id_to_name(monster.id), monsters.path());
+    }
+    Ok(())
+}
+
+fn resume_battle(world: &mut World, save: &BattleSave) -> anyhow::Result<()> {
+    check_resume(world, save)?;
+
+    let content = battle_content(world);
+    for (path, hash) in &save.content {
+        if !content.contains(&(path.clone(), *hash)) {
+            warn!("{} changed since the battle was saved", path);
+        }
+    }
+
+    world.insert_resource(BattleRng::resume(save.seed, save.rng_word_pos));
+    world.get_resource_mut::<BattleClock>().unwrap().resume_at(save.tick + 1);
+    world.get_resource_mut::<MapStage>().unwrap().restore(&save.stage);
+    let mut base = world.get_resource_mut::<BattleBase>().unwrap();
+    base.lives = save.lives;
+    base.gold = save.gold;
+    let mut summary = world.get_resource_mut::<BattleSummary>().unwrap();
+    summary.kills = save.kills;
+    summary.leaks = save.leaks;
+
+    //towers are older than every living monster, they are built with their abilities before the monsters come back
+    let mut slots = world.get_resource_mut::<BuildSlots>().unwrap();
+    for tower in &save.towers {
+        slots.set_occupied(tower.slot, true);
+    }
+    let tower_entities = save.towers.iter().map(|tower| {
+        world.spawn().insert_bundle((tower.transform.to_transform(), GlobalTransform::identity(), CreateAttackerReq { id: tower.id, slot: tower.slot, spent: tower.spent, abilities: tower.abilities.clone() })).id()
+    }).collect::<Vec<_>>();
+    let mut spawn_attackers = spawn_attacker_system.system();
+    spawn_attackers.initialize(world);
+    spawn_attackers.run((), world);
+    spawn_attackers.apply_buffers(world);
+
+    let mut entities = HashMap::new();
+    world.resource_scope(|world, table: Mut<TableData<MonsterConfig>>| {
+        for monster in &save.monsters {
+            let config = table.index(monster.id);
+            let mut attrs = Attrs::load_from_config(&config.attrs);
+            attrs.restore_values(&monster.attrs);
+            let move_with = MoveWithMapPath { road_index: monster.road_index, target_point_index: monster.target_point_index, speed: monster.speed };
+            let entity = world.spawn().insert_bundle(monster_bundle(monster.id, config, monster.transform.to_transform(), move_with, attrs)).id();
+            entities.insert(monster.entity, entity);
+        }
+    });
+
+    //the towers go on with their priority and kills and their abilities with their targets once the monsters are back
+    let towers = save.towers.iter().map(|tower| tower.entity).zip(tower_entities.iter().copied()).collect::<HashMap<_, _>>();
+    for (tower, entity) in save.towers.iter().zip(tower_entities) {
+        *world.get_mut::<TargetPriority>(entity).unwrap() = tower.priority.clone();
+        world.get_mut::<Attacker>(entity).unwrap().kills = tower.kills;
+        let children = world.get::<Children>(entity).map_or(Vec::new(), |children| children.to_vec());
+        let (mut shoot, mut channel) = (tower.shoot.iter(), tower.channel.iter());
+        for child in children {
+            if let Some(mut runtime) = world.get_mut::<ShootAbilityRuntime>(child) {
+                if let Some(saved) = shoot.next() {
+                    runtime.target = saved.target.and_then(|e| entities.get(&e).copied());
+                    runtime.ammo = saved.ammo;
+                    runtime.cool_down = saved.cool_down;
+                    runtime.reload = saved.reload;
+                }
+            }
+            if let Some(mut runtime) = world.get_mut::<ChannelAbilityRuntime>(child) {
+                if let Some(saved) = channel.next() {
+                    runtime.target = saved.target.and_then(|e| entities.get(&e).copied());
+                    runtime.value = saved.value;
+                    runtime.exhausted = saved.exhausted;
+                    runtime.charge = saved.charge;
+                }
+            }
+        }
+    }
+
+    for force in &save.forces {
+        let target = match force.target {
+            ForceTargetSave::Entity(e) => {
+                //the target died in the tick the battle was saved
+                match entities.get(&e) {
+                    Some(entity) => ForceTarget::Entity(*entity),
+                    None => continue,
+                }
+            }
+            ForceTargetSave::Position(p) => {
+                ForceTarget::Position(Vec3::from(p))
+            }
+        };
+        //a sold tower keeps no kills
+        let source = force.source.and_then(|e| towers.get(&e).copied());
+        world.spawn().insert_bundle((force.transform.to_transform(), GlobalTransform::identity(), CreateForceReq { id: force.id, source }, target));
+    }
+
+    info!("battle resumed at tick {}, {} monsters, {} towers", save.tick, save.monsters.len(), save.towers.len());
+    Ok(())
+}
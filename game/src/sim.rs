@@ -0,0 +1,254 @@
+use bevy::ecs::schedule::ShouldRun;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use crate::attacker::{Attacker, ChannelAbilityRuntime, ShootAbilityRuntime};
+use crate::attrs::Attrs;
+use crate::battle::{BattleBase, BattleSummary};
+use crate::force::CreateForceReq;
+use crate::monster::{Monster, MoveWithMapPath};
+use crate::stage::MapStage;
+use crate::game::GameState;
+use crate::prelude::*;
+
+pub const BATTLE_STEP: f32 = 1f32 / 60f32;
+
+//a slow frame catches up with at most this many ticks, the battle slows down instead of stalling the window
+const MAX_TICKS_PER_UPDATE: f32 = 8f32;
+
+//every battle system runs here once per tick, single threaded so no two systems ever overlap
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct BattleStage;
+
+//the phases of a tick, every battle system has one and runs after the phase before it
+//the stage sorts unordered systems differently on every start and applies commands in system order, so one unordered system is enough to change the entities of a battle
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum BattleSystem {
+    //GlobalTransform catches up with the Transform of the last tick
+    Propagate,
+    Input,
+    SpawnAttackers,
+    Stage,
+    Move,
+    HitQuery,
+    Abilities,
+    //after the shots, both may draw from BattleRng
+    Beams,
+    Forces,
+    Attrs,
+    Death,
+    Leak,
+    Outcome,
+}
+
+//the time battle systems advance by, always one fixed step per tick
+//with a window the ticks follow Time, headless each update is exactly one tick
+pub struct BattleClock {
+    step: f32,
+    follow_time: bool,
+    accumulator: f32,
+    tick: u64,
+    looping: bool,
+}
+
+impl BattleClock {
+    pub fn real_time(step: f32) -> Self {
+        BattleClock { step, follow_time: true, accumulator: 0f32, tick: 0, looping: false }
+    }
+
+    pub fn fixed(step: f32) -> Self {
+        BattleClock { step, follow_time: false, accumulator: 0f32, tick: 0, looping: false }
+    }
+
+    pub fn set_step(&mut self, step: f32) {
+        self.step = step;
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.step
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.tick as f64 * self.step as f64
+    }
+
+    //a resumed battle goes on counting from the tick it was saved at
+    pub(crate) fn resume_at(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+}
+
+//ticks only while playing, so tick 1 is always the first tick of the battle, and stops as soon as the outcome is known
+pub fn battle_tick_criteria(time: Res<Time>,
+                            state: Res<State<GameState>>,
+                            summary: Option<Res<BattleSummary>>,
+                            mut clock: ResMut<BattleClock>) -> ShouldRun {
+    if state.current() != &GameState::Playing || summary.map_or(false, |s| s.outcome.is_some()) {
+        clock.accumulator = 0f32;
+        return ShouldRun::No;
+    }
+
+    if !clock.looping {
+        let add = if clock.follow_time { time.delta_seconds() } else { clock.step };
+        clock.accumulator = (clock.accumulator + add).min(clock.step * MAX_TICKS_PER_UPDATE);
+    }
+
+    if clock.accumulator >= clock.step {
+        clock.accumulator -= clock.step;
+        clock.tick += 1;
+        clock.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
+
+//all gameplay randomness draws from here, the same seed replays the same battle
+//ChaCha12 is what StdRng uses, spelled out so a saved battle can store how far the stream went
+pub struct BattleRng {
+    seed: u64,
+    rng: ChaCha12Rng,
+}
+
+impl BattleRng {
+    pub fn new(seed: u64) -> Self {
+        BattleRng { seed, rng: ChaCha12Rng::seed_from_u64(seed) }
+    }
+
+    //the stream of seed continued from word_pos
+    pub fn resume(seed: u64, word_pos: u64) -> Self {
+        let mut rng = Self::new(seed);
+        rng.rng.set_word_pos(word_pos as u128);
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    //words drawn so far, u64 is far more than a battle ever draws
+    pub fn word_pos(&self) -> u64 {
+        self.rng.get_word_pos() as u64
+    }
+}
+
+impl RngCore for BattleRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+//digest of the simulated monsters, towers, forces, stage, counters and rng, two runs stayed bit-identical when their hashes match
+//entity ids and view-only entities are left out, the records are hashed in the order of their bytes
+//so a windowed run, a headless run and a resumed battle hash the same state alike
+pub fn battle_state_hash(world: &mut World) -> u64 {
+    fn push_floats(bytes: &mut Vec<u8>, floats: impl IntoIterator<Item = f32>) {
+        for f in floats {
+            bytes.extend(f.to_bits().to_le_bytes());
+        }
+    }
+    fn push_attrs(bytes: &mut Vec<u8>, attrs: Option<&Attrs>) {
+        for (id, value) in attrs.into_iter().flat_map(Attrs::sorted_values) {
+            bytes.extend(id.to_le_bytes());
+            bytes.extend(value.to_bits().to_le_bytes());
+        }
+    }
+
+    let mut records = Vec::new();
+    for (monster, transform, path, attrs) in world.query::<(&Monster, &Transform, Option<&MoveWithMapPath>, Option<&Attrs>)>().iter(world) {
+        let mut bytes = vec![0u8];
+        bytes.extend(monster.id.to_le_bytes());
+        push_floats(&mut bytes, transform.translation.to_array());
+        if let Some(path) = path {
+            bytes.extend((path.road_index as u64).to_le_bytes());
+            bytes.extend((path.target_point_index as u64).to_le_bytes());
+            push_floats(&mut bytes, [path.speed]);
+        }
+        push_attrs(&mut bytes, attrs);
+        records.push(bytes);
+    }
+    for (attacker, transform, attrs) in world.query::<(&Attacker, &Transform, Option<&Attrs>)>().iter(world) {
+        let mut bytes = vec![1u8];
+        bytes.extend(attacker.id.to_le_bytes());
+        bytes.extend((attacker.slot as u64).to_le_bytes());
+        bytes.extend(attacker.kills.to_le_bytes());
+        bytes.extend(attacker.spent.to_le_bytes());
+        for id in attacker.abilities.iter() {
+            bytes.extend(id.to_le_bytes());
+        }
+        push_floats(&mut bytes, transform.translation.to_array());
+        push_attrs(&mut bytes, attrs);
+        records.push(bytes);
+    }
+    //ability runtimes belong to the tower in their slot
+    let slot_of = |world: &World, parent: &Parent| world.get::<Attacker>(parent.0).map_or(u64::MAX, |attacker| attacker.slot as u64);
+    for (parent, runtime) in world.query::<(&Parent, &ShootAbilityRuntime)>().iter(world) {
+        let mut bytes = vec![2u8];
+        bytes.extend(slot_of(world, parent).to_le_bytes());
+        bytes.extend(runtime.ammo.to_le_bytes());
+        push_floats(&mut bytes, [runtime.cool_down, runtime.reload]);
+        records.push(bytes);
+    }
+    for (parent, runtime) in world.query::<(&Parent, &ChannelAbilityRuntime)>().iter(world) {
+        let mut bytes = vec![3u8];
+        bytes.extend(slot_of(world, parent).to_le_bytes());
+        push_floats(&mut bytes, [runtime.value, runtime.charge]);
+        bytes.push(runtime.exhausted as u8);
+        records.push(bytes);
+    }
+    for (req, transform) in world.query::<(&CreateForceReq, &Transform)>().iter(world) {
+        let mut bytes = vec![4u8];
+        bytes.extend(req.id.to_le_bytes());
+        push_floats(&mut bytes, transform.translation.to_array());
+        records.push(bytes);
+    }
+    if let Some(base) = world.get_resource::<BattleBase>() {
+        let mut bytes = vec![5u8];
+        bytes.extend(base.lives.to_le_bytes());
+        bytes.extend(base.gold.to_le_bytes());
+        records.push(bytes);
+    }
+    if let Some(summary) = world.get_resource::<BattleSummary>() {
+        let mut bytes = vec![6u8];
+        bytes.extend(summary.kills.to_le_bytes());
+        bytes.extend(summary.leaks.to_le_bytes());
+        bytes.push(summary.outcome.map_or(0u8, |outcome| outcome as u8 + 1));
+        records.push(bytes);
+    }
+    //the queues and waves still to come, in the form a save keeps them
+    if let Some(stage) = world.get_resource::<MapStage>() {
+        let mut bytes = vec![7u8];
+        bytes.extend(bincode::serialize(&stage.progress()).expect("failed to serialize the stage progress"));
+        records.push(bytes);
+    }
+    if let Some(rng) = world.get_resource::<BattleRng>() {
+        let mut bytes = vec![8u8];
+        bytes.extend(rng.seed().to_le_bytes());
+        bytes.extend(rng.word_pos().to_le_bytes());
+        records.push(bytes);
+    }
+    records.sort();
+
+    let mut bytes = Vec::new();
+    for record in records {
+        bytes.extend((record.len() as u64).to_le_bytes());
+        bytes.extend(record);
+    }
+    hashtoollib::hash_bytes(&bytes)
+}
@@ -1,12 +1,17 @@
 ﻿use bevy::prelude::*;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use itertools::Itertools;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 use crate::attrs::Attrs;
-use crate::hit_query::HitBounds;
+use crate::difficulty::{DifficultyConfig, DifficultyMode};
+use crate::endless::{EndlessConfig, EndlessMode, EndlessWaves, ENDLESS_LOOKAHEAD};
+use crate::sim::{BattleClock, BattleRng};
 
 use crate::map::MapConfigAsset;
-use crate::monster::{MonsterConfig, MoveWithMapPath};
-use crate::proto::PathEditor::{MapConfig, PathWayPointData};
+use crate::monster::{monster_bundle, MonsterConfig, MoveWithMapPath};
+use crate::placement::BuildSlots;
+use crate::proto::PathEditor::{MapConfig, PathWayPointData, Wave, WaveTiming};
 use crate::rand_position;
 use crate::table::TableData;
 
@@ -17,16 +22,101 @@ pub struct MapStage {
     waiting_queues: Vec<usize>,
     working_queues: Vec<MapStageWorkingQueue>,
     past_time: f32,
+    all_finished_sent: bool,
+    //an endless stage has one queue the generator keeps filling, it never finishes
+    endless: Option<EndlessWaves>,
+    scaled_attrs: Vec<u64>,
+    //kept for the waves the endless generator adds later
+    difficulty: DifficultyConfig,
 }
 
-#[derive(Default)]
+//sent by update_stage_system as the waves run, queue and wave are the indices in the MapConfig
+pub struct WaveQueueStarted {
+    pub queue: usize,
+}
+
+pub struct WaveStarted {
+    pub queue: usize,
+    pub wave: usize,
+    pub unit: u64,
+}
+
+pub struct WaveSpawnedUnit {
+    pub queue: usize,
+    pub wave: usize,
+    pub unit: u64,
+    pub entity: Entity,
+}
+
+pub struct WaveFinished {
+    pub queue: usize,
+    pub wave: usize,
+}
+
+//the last wave of every queue is done spawning, the monsters may still be alive
+pub struct AllWavesFinished;
+
+//everything MapStage changes while running, the roads and waves come from the map again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapStageProgress {
+    waiting_queues: Vec<usize>,
+    working_queues: Vec<MapStageWorkingQueue>,
+    past_time: f32,
+    all_finished_sent: bool,
+    //(seed, generated waves) of the endless generator
+    endless: Option<(u64, usize)>,
+}
+
+//what MapStage::advance asks for, update_stage_system turns it into the events and the spawned monsters
+#[derive(Debug, PartialEq)]
+pub enum StageAction {
+    QueueStarted { queue: usize },
+    WaveStarted { queue: usize, wave: usize, unit: u64 },
+    Spawn { queue: usize, wave: usize, unit: u64, road_idx: usize, count: u32, attr_scale: f32 },
+    WaveFinished { queue: usize, wave: usize },
+    AllWavesFinished,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct MapStageWorkingQueue {
     queue_idx: usize,
     waiting_wave_idx: usize,
     working_waves: Vec<MapStageWorkingWave>,
+    //when the queue and its last wave were due, not the tick they were noticed, so relative waits never drift
+    start_time: f32,
+    last_wave_time: f32,
+    //seconds the player skipped by calling waves early, the queue runs this far ahead of the battle
+    skipped: f32,
+}
+
+//a wave called before it was due, the reward is already paid out
+#[derive(Debug, PartialEq)]
+pub struct EarlyWaveCall {
+    pub wave: usize,
+    pub seconds: f32,
+    pub reward: u32,
+}
+
+//a wave that has not started yet, count is every unit it will spawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpcomingWave {
+    pub queue: usize,
+    pub wave: usize,
+    pub unit: u64,
+    pub count: u32,
+    pub path: usize,
+    pub seconds_left: f32,
+    //what calling it now pays, only the next wave of a started queue can be called
+    pub early_call_reward: u32,
 }
 
+//refreshed every tick, ordered by the time the waves start
 #[derive(Default)]
+pub struct WavePreview {
+    pub waves: Vec<UpcomingWave>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct MapStageWorkingWave {
     spawn_road_idx: usize,
     work_time: f32,
@@ -34,6 +124,20 @@ pub struct MapStageWorkingWave {
     spawn_cool_down: f32,
 }
 
+impl MapStageWorkingWave {
+    //returns (spawn, finished)
+    fn update(&mut self, wave: &MapStageWave, delta: f32) -> (bool, bool) {
+        self.work_time += delta;
+        let spawn = self.spawn_cool_down <= 0f32;
+        if spawn {
+            self.spawn_cool_down = wave.spawn_cool_down;
+        } else {
+            self.spawn_cool_down -= delta;
+        }
+        (spawn, self.work_time >= wave.duration)
+    }
+}
+
 mod path_config_util {
     use bevy::math::vec3;
 
@@ -52,7 +156,7 @@ mod path_config_util {
 }
 
 impl MapStage {
-    pub fn create(config: &MapConfig) -> Self {
+    pub fn create(config: &MapConfig, difficulty: &DifficultyConfig) -> Self {
         fn get_dir(points: &Vec<PathWayPointData>, idx_a: usize, idx_b: usize, last_dir: Option<Vec3>) ->
         (Vec3, (Vec3, f32), (Vec3, f32)) {
             let pa = &points[idx_a];
@@ -152,22 +256,14 @@ impl MapStage {
         let mut waiting_queues = Vec::new();
 
         let queues = config.wave_queues.iter().enumerate().map(|(queue_idx, wq)| {
-            let waves = wq.waves.iter().map(|ws| {
-                MapStageWave {
-                    wait_time: ws.wait_time,
-                    per_spawn_unit_count: ws.per_spawn_count as _,
-                    spawn_cool_down: ws.spawn_cool_down,
-                    duration: ws.duration,
-                    uint_name: ws.unit,
-                    path_index: ws.path_index as _,
-                }
-            }).collect::<Vec<_>>();
+            let waves = wq.waves.iter().map(|ws| MapStageWave::create(ws, 1f32, difficulty)).collect::<Vec<_>>();
 
             waiting_queues.push(queue_idx);
 
             StageWaveQueue {
                 waves,
                 wait_time: wq.wait_time,
+                timing: wq.timing,
             }
         }).collect::<Vec<_>>();
 
@@ -178,7 +274,217 @@ impl MapStage {
             queues,
             waiting_queues,
             working_queues: Default::default(),
+            all_finished_sent: false,
+            endless: None,
+            scaled_attrs: Vec::new(),
+            difficulty: difficulty.clone(),
+        }
+    }
+
+    //the roads of the map with the waves of the generator instead of the queues of the map
+    pub fn create_endless(config: &MapConfig, difficulty: &DifficultyConfig, endless: EndlessWaves) -> Self {
+        let mut stage = Self::create(config, difficulty);
+        stage.queues = vec![StageWaveQueue { waves: Vec::new(), wait_time: 0f32, timing: WaveTiming::RelativeToPreviousWave }];
+        stage.waiting_queues = vec![0];
+        stage.scaled_attrs = endless.scaled_attrs();
+        stage.endless = Some(endless);
+        stage.fill_endless();
+        stage
+    }
+
+    fn fill_endless(&mut self) {
+        let endless = match &mut self.endless {
+            Some(endless) => endless,
+            None => return,
+        };
+        let next_wave = self.working_queues.first().map_or(0, |working| working.waiting_wave_idx);
+        let queue = &mut self.queues[0];
+        while queue.waves.len() < next_wave + ENDLESS_LOOKAHEAD {
+            let (wave, attr_scale) = endless.next_wave();
+            queue.waves.push(MapStageWave::create(&wave, attr_scale, &self.difficulty));
+        }
+    }
+
+    //the attrs an endless stage scales on its monsters
+    pub fn scaled_attrs(&self) -> &[u64] {
+        &self.scaled_attrs
+    }
+
+    pub fn progress(&self) -> MapStageProgress {
+        MapStageProgress {
+            waiting_queues: self.waiting_queues.clone(),
+            working_queues: self.working_queues.clone(),
+            past_time: self.past_time,
+            all_finished_sent: self.all_finished_sent,
+            endless: self.endless.as_ref().map(|endless| (endless.seed(), endless.generated())),
+        }
+    }
+
+    //continues from the progress of a stage created from the same map and difficulty
+    pub fn restore(&mut self, progress: &MapStageProgress) {
+        if let (Some(endless), Some((seed, generated))) = (&mut self.endless, progress.endless) {
+            endless.restart(seed);
+            let queue = &mut self.queues[0];
+            queue.waves.clear();
+            for _ in 0..generated {
+                let (wave, attr_scale) = endless.next_wave();
+                queue.waves.push(MapStageWave::create(&wave, attr_scale, &self.difficulty));
+            }
+        }
+
+        self.waiting_queues = progress.waiting_queues.clone();
+        self.working_queues = progress.working_queues.clone();
+        self.past_time = progress.past_time;
+        self.all_finished_sent = progress.all_finished_sent;
+    }
+
+    //every queue started and all of their waves done spawning
+    pub fn is_finished(&self) -> bool {
+        self.waiting_queues.is_empty() && self.working_queues.is_empty()
+    }
+
+    //moves the stage delta seconds on, a queue starts wait_time seconds into the battle
+    //and the wait_time of its waves counts as its WaveTiming says:
+    //  Absolute                seconds since the battle started
+    //  RelativeToQueue         seconds since the queue started
+    //  RelativeToPreviousWave  seconds since the previous wave of the queue started, the queue start for the first wave
+    //every wave that is due starts in the same tick
+    pub fn advance(&mut self, delta: f32) -> Vec<StageAction> {
+        let mut actions = Vec::new();
+        self.past_time += delta;
+        let past_time = self.past_time;
+
+        //check waiting
+        let queues = &self.queues;
+        let working_queues = &mut self.working_queues;
+        self.waiting_queues.retain(|queue_idx| {
+            let wait_time = queues[*queue_idx].wait_time;
+            let past = past_time >= wait_time;
+            if past {
+                working_queues.push(MapStageWorkingQueue {
+                    queue_idx: *queue_idx,
+                    start_time: wait_time,
+                    last_wave_time: wait_time,
+                    ..Default::default()
+                });
+                actions.push(StageAction::QueueStarted { queue: *queue_idx });
+            }
+            !past
+        });
+
+        //check working
+        for i in (0..self.working_queues.len()).rev() {
+            let working = &mut self.working_queues[i];
+            let queue = &self.queues[working.queue_idx];
+            while let Some(waiting_wave) = queue.waves.get(working.waiting_wave_idx) {
+                let due_time = queue.due_time(working.waiting_wave_idx, working.start_time, working.last_wave_time);
+                if past_time + working.skipped < due_time {
+                    break;
+                }
+
+                //add wave
+                working.last_wave_time = due_time;
+                working.working_waves.push(MapStageWorkingWave {
+                    spawn_road_idx: 0,
+                    wave_idx: working.waiting_wave_idx,
+                    work_time: 0f32,
+                    spawn_cool_down: 0f32,
+                });
+                actions.push(StageAction::WaveStarted { queue: working.queue_idx, wave: working.waiting_wave_idx, unit: waiting_wave.uint_name });
+                working.waiting_wave_idx += 1;
+            }
+
+            if working.waiting_wave_idx >= queue.waves.len() && working.working_waves.is_empty() {
+                self.working_queues.remove(i);
+            }
+        }
+
+        if !self.all_finished_sent && self.is_finished() {
+            self.all_finished_sent = true;
+            actions.push(StageAction::AllWavesFinished);
+        }
+
+        //update wave
+        for queue in &mut self.working_queues {
+            let waves = &self.queues[queue.queue_idx].waves;
+            for i in (0..queue.working_waves.len()).rev() {
+                let working = &mut queue.working_waves[i];
+                let wave = &waves[working.wave_idx];
+                let (spawn, finished) = working.update(wave, delta);
+                if spawn {
+                    let path_2_road = &self.path_2_road[wave.path_index];
+                    let road_idx = path_2_road.start_idx + working.spawn_road_idx;
+                    working.spawn_road_idx = (working.spawn_road_idx + 1) % path_2_road.count;
+                    actions.push(StageAction::Spawn {
+                        queue: queue.queue_idx,
+                        wave: working.wave_idx,
+                        unit: wave.uint_name,
+                        road_idx,
+                        count: wave.per_spawn_unit_count,
+                        attr_scale: wave.attr_scale,
+                    });
+                }
+
+                if finished {
+                    actions.push(StageAction::WaveFinished { queue: queue.queue_idx, wave: working.wave_idx });
+                    queue.working_waves.remove(i);
+                }
+            }
+        }
+
+        self.fill_endless();
+        actions
+    }
+
+    //starts the next wave of a started queue right away, the later waves of the queue move up by the same time
+    //pays the early_call_reward of the wave for every second it was called early
+    pub fn call_next_wave(&mut self, queue_idx: usize) -> Option<EarlyWaveCall> {
+        let working = self.working_queues.iter_mut().find(|working| working.queue_idx == queue_idx)?;
+        let queue = &self.queues[queue_idx];
+        let wave = queue.waves.get(working.waiting_wave_idx)?;
+        let seconds = queue.due_time(working.waiting_wave_idx, working.start_time, working.last_wave_time) - (self.past_time + working.skipped);
+        if seconds <= 0f32 {
+            return None;
+        }
+
+        working.skipped += seconds;
+        Some(EarlyWaveCall { wave: working.waiting_wave_idx, seconds, reward: (wave.early_call_reward * seconds) as u32 })
+    }
+
+    //every wave still to start, step is the tick length the waves will run with
+    pub fn upcoming_waves(&self, step: f32) -> Vec<UpcomingWave> {
+        let started = self.working_queues.iter().map(|working| {
+            (working.queue_idx, working.waiting_wave_idx, working.start_time, working.last_wave_time, working.skipped)
+        });
+        let waiting = self.waiting_queues.iter().map(|queue_idx| {
+            let wait_time = self.queues[*queue_idx].wait_time;
+            (*queue_idx, 0, wait_time, wait_time, 0f32)
+        });
+
+        let mut upcoming = Vec::new();
+        for (queue_idx, first_wave, start_time, mut last_wave_time, skipped) in started.chain(waiting) {
+            let queue = &self.queues[queue_idx];
+            let now = self.past_time + skipped;
+            //a wave never starts before its queue or the wave in front of it
+            let mut start = start_time.max(now);
+            for (wave_idx, wave) in queue.waves.iter().enumerate().skip(first_wave) {
+                let due_time = queue.due_time(wave_idx, start_time, last_wave_time);
+                last_wave_time = due_time;
+                start = start.max(due_time);
+                let callable = wave_idx == first_wave && start_time <= now;
+                upcoming.push(UpcomingWave {
+                    queue: queue_idx,
+                    wave: wave_idx,
+                    unit: wave.uint_name,
+                    count: wave.unit_count(step),
+                    path: wave.path_index,
+                    seconds_left: start - now,
+                    early_call_reward: if callable { (wave.early_call_reward * (start - now)) as u32 } else { 0 },
+                });
+            }
         }
+        upcoming.sort_by(|a, b| a.seconds_left.total_cmp(&b.seconds_left).then(a.queue.cmp(&b.queue)));
+        upcoming
     }
 }
 
@@ -191,11 +497,22 @@ pub struct MapStageRoad {
     pub points: Vec<MapStageRoadPoint>,
 }
 
+impl MapStageRoad {
+    //what is left to walk from pos on to the end, heading for the point at target_point_index first
+    pub fn remaining_distance(&self, target_point_index: usize, pos: Vec3) -> f32 {
+        let next = match self.points.get(target_point_index) {
+            Some(point) => point.pos,
+            None => return 0f32,
+        };
+        let rest = self.points[target_point_index..].windows(2).map(|w| w[0].pos.distance(w[1].pos)).sum::<f32>();
+        pos.distance(next) + rest
+    }
+}
+
 pub struct MapStageRoadPoint {
     pub pos: Vec3,
 }
 
-#[allow(dead_code)]
 pub struct MapStageWave {
     wait_time: f32,
     uint_name: u64,
@@ -203,16 +520,128 @@ pub struct MapStageWave {
     spawn_cool_down: f32,
     duration: f32,
     path_index: usize,
+    early_call_reward: f32,
+    //endless waves grow stronger, monsters of a map wave keep the attrs of their config
+    attr_scale: f32,
+}
+
+impl MapStageWave {
+    fn create(ws: &Wave, attr_scale: f32, difficulty: &DifficultyConfig) -> Self {
+        MapStageWave {
+            wait_time: ws.wait_time,
+            per_spawn_unit_count: difficulty.scale_per_spawn_count(ws.per_spawn_count as _),
+            spawn_cool_down: ws.spawn_cool_down * difficulty.spawn_cool_down,
+            duration: ws.duration,
+            uint_name: ws.unit,
+            path_index: ws.path_index as _,
+            early_call_reward: ws.early_call_reward,
+            attr_scale,
+        }
+    }
+
+    //what the stage will spawn in ticks of step, a wave spawns on its first tick and then once every cool down
+    //the tick after a spawn still counts as cool down, so a spawn takes ceil(cool down / step) + 1 ticks
+    fn unit_count(&self, step: f32) -> u32 {
+        let ticks = (self.duration / step).ceil().max(1f32) as u32;
+        let period = match self.spawn_cool_down > 0f32 {
+            true => {
+                (self.spawn_cool_down / step).ceil() as u32 + 1
+            }
+            false => {
+                1
+            }
+        };
+        ((ticks - 1) / period + 1) * self.per_spawn_unit_count
+    }
 }
 
 pub struct StageWaveQueue {
     waves: Vec<MapStageWave>,
     wait_time: f32,
+    timing: WaveTiming,
+}
+
+impl StageWaveQueue {
+    //when the wave is due, in seconds since the battle started if the queue never skipped
+    fn due_time(&self, wave_idx: usize, start_time: f32, last_wave_time: f32) -> f32 {
+        let wait_time = self.waves[wave_idx].wait_time;
+        match self.timing {
+            WaveTiming::Absolute => {
+                wait_time
+            }
+            WaveTiming::RelativeToQueue => {
+                start_time + wait_time
+            }
+            WaveTiming::RelativeToPreviousWave => {
+                last_wave_time + wait_time
+            }
+        }
+    }
 }
 
-pub fn init_stage_system(mut commands: Commands, res: Res<Assets<MapConfigAsset>>) {
+pub fn init_stage_system(mut commands: Commands,
+                         res: Res<Assets<MapConfigAsset>>,
+                         endless_mode: Option<Res<EndlessMode>>,
+                         endless_table: Res<TableData<EndlessConfig>>,
+                         difficulty_mode: Option<Res<DifficultyMode>>,
+                         difficulty_table: Res<TableData<DifficultyConfig>>,
+                         mut rng: ResMut<BattleRng>) {
     let (_, config) = res.iter().next().expect("no map config loaded");
-    commands.insert_resource(MapStage::create(&config.config));
+    let difficulty = match difficulty_mode {
+        Some(mode) => {
+            match difficulty_table.find(hashtoollib::hash(&mode.name)) {
+                Some(difficulty) => {
+                    info!("battle difficulty {}", mode.name);
+                    difficulty.clone()
+                }
+                None => {
+                    error!("no difficulty '{}' in {}, the battle runs without a preset", mode.name, difficulty_table.path());
+                    DifficultyConfig::default()
+                }
+            }
+        }
+        None => {
+            DifficultyConfig::default()
+        }
+    };
+
+    //a mode that can not make waves is reported, the battle then runs the waves of the map
+    let endless = endless_mode.and_then(|mode| match endless_table.find(hashtoollib::hash(&mode.name)) {
+        Some(endless) if endless.units.is_empty() => {
+            error!("endless mode '{}' has no units, the battle runs the waves of the map", mode.name);
+            None
+        }
+        Some(endless) if !endless.fits_paths(config.config.paths.len()) => {
+            error!("endless mode '{}' has no paths on this map, the battle runs the waves of the map", mode.name);
+            None
+        }
+        Some(endless) => {
+            info!("endless battle with {}", mode.name);
+            Some(endless)
+        }
+        None => {
+            error!("no endless mode '{}' in {}, the battle runs the waves of the map", mode.name, endless_table.path());
+            None
+        }
+    });
+
+    let stage = match endless {
+        Some(endless) => {
+            let waves = EndlessWaves::new(endless, config.config.paths.len(), rng.next_u64());
+            MapStage::create_endless(&config.config, &difficulty, waves)
+        }
+        None => {
+            MapStage::create(&config.config, &difficulty)
+        }
+    };
+    commands.insert_resource(BuildSlots::create(&config.config, &stage.roads));
+    commands.insert_resource(stage);
+    commands.insert_resource(difficulty);
+    commands.insert_resource(WavePreview::default());
+}
+
+pub fn update_wave_preview_system(map_stage: Res<MapStage>, clock: Res<BattleClock>, mut preview: ResMut<WavePreview>) {
+    preview.waves = map_stage.upcoming_waves(clock.delta_seconds());
 }
 
 
@@ -229,100 +658,276 @@ pub fn draw_stage_roads(map_stage: Res<MapStage>, mut lines: ResMut<bevy_prototy
 
 pub fn update_stage_system(mut commands: Commands,
                            mut map_stage: ResMut<MapStage>,
-                           map_assets: Res<Assets<MapConfigAsset>>,
-                           asset_server: Res<AssetServer>,
                            monster_table: Res<TableData<MonsterConfig>>,
-                           time: Res<Time>) {
-    let map_stage = map_stage.deref_mut();
-    let delta = time.delta().as_secs_f32();
-    map_stage.past_time += delta;
+                           difficulty: Res<DifficultyConfig>,
+                           clock: Res<BattleClock>,
+                           mut rng: ResMut<BattleRng>,
+                           mut queue_started: EventWriter<WaveQueueStarted>,
+                           mut wave_started: EventWriter<WaveStarted>,
+                           mut unit_spawned: EventWriter<WaveSpawnedUnit>,
+                           mut wave_finished: EventWriter<WaveFinished>,
+                           mut all_finished: EventWriter<AllWavesFinished>) {
+    for action in map_stage.advance(clock.delta_seconds()) {
+        match action {
+            StageAction::QueueStarted { queue } => {
+                queue_started.send(WaveQueueStarted { queue });
+            }
+            StageAction::WaveStarted { queue, wave, unit } => {
+                wave_started.send(WaveStarted { queue, wave, unit });
+            }
+            StageAction::Spawn { queue, wave, unit, road_idx, count, attr_scale } => {
+                let road = &map_stage.roads[road_idx];
+                let first_point = rand_position(&road.points[0].pos, 0.25f32, &mut rng);
+                let monster_config = monster_table.index(unit);
+
+                for _ in 0..count {
+                    let pos = rand_position(&first_point, 3f32, &mut rng);
+                    let mut attrs = Attrs::load_from_config(&monster_config.attrs);
+                    difficulty.scale_attrs(&mut attrs);
+                    attrs.scale(map_stage.scaled_attrs(), attr_scale);
+                    let move_with = MoveWithMapPath {
+                        road_index: road_idx,
+                        target_point_index: 1,
+                        speed: monster_config.move_speed * difficulty.move_speed,
+                    };
+                    let entity = commands.spawn_bundle(monster_bundle(unit, monster_config, Transform::from_translation(pos), move_with, attrs)).id();
+                    unit_spawned.send(WaveSpawnedUnit { queue, wave, unit, entity });
+                }
+            }
+            StageAction::WaveFinished { queue, wave } => {
+                wave_finished.send(WaveFinished { queue, wave });
+            }
+            StageAction::AllWavesFinished => {
+                all_finished.send(AllWavesFinished);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::PathEditor::{MapVector3, PathData, Wave, WaveQueue};
+    use crate::endless::{EndlessCurvePoint, EndlessUnitConfig};
+    use crate::StringId;
 
-    let (_, config_asset) = map_assets.iter().next().expect("no map config loaded");
-    let config = &config_asset.config;
+    //quarter seconds add up exactly, so a wave due at 2.0 starts on tick 8
+    const STEP: f32 = 0.25f32;
 
-    //check waiting
-    map_stage.waiting_queues.retain(|queue_idx| {
-        let past = map_stage.past_time >= map_stage.queues[*queue_idx].wait_time;
-        if past {
-            map_stage.working_queues.push(MapStageWorkingQueue { queue_idx: *queue_idx, waiting_wave_idx: 0, working_waves: Default::default() });
+    fn wave(wait_time: f32) -> Wave {
+        Wave { wait_time, unit: 1, spawn_cool_down: 0.5f32, duration: 1f32, per_spawn_count: 2, path_index: 0, early_call_reward: 10f32 }
+    }
+
+    fn config(queues: Vec<(f32, WaveTiming, Vec<Wave>)>) -> MapConfig {
+        let point = |x: f32| PathWayPointData { position: Some(MapVector3 { x, y: 0f32, z: 0f32 }), reach_range: 1f32 };
+        MapConfig {
+            wave_queues: queues.into_iter().map(|(wait_time, timing, waves)| WaveQueue { wait_time, waves, timing }).collect(),
+            paths: vec![PathData { points: vec![point(0f32), point(10f32)] }],
+            ..Default::default()
         }
-        !past
-    });
+    }
 
-    //check working
-    for i in (0..map_stage.working_queues.len()).rev() {
-        let working = &mut map_stage.working_queues[i];
-        let mut finish = false;
-        let queue = &map_stage.queues[working.queue_idx];
-        if working.waiting_wave_idx > queue.waves.len() - 1 {
-            finish = working.working_waves.len() == 0;
-        } else {
-            let waiting_wave = &queue.waves[working.waiting_wave_idx];
-            if map_stage.past_time >= waiting_wave.wait_time {
-                //add wave
-                working.working_waves.push(MapStageWorkingWave {
-                    spawn_road_idx: 0,
-                    wave_idx: working.waiting_wave_idx,
-                    work_time: 0f32,
-                    spawn_cool_down: 0f32,
-                });
-                working.waiting_wave_idx += 1;
+    //(tick, action) of every action until the stage finished
+    fn run(config: &MapConfig) -> Vec<(u32, StageAction)> {
+        let mut stage = MapStage::create(config, &DifficultyConfig::default());
+        let mut actions = Vec::new();
+        for tick in 1..1000 {
+            actions.extend(stage.advance(STEP).into_iter().map(|action| (tick, action)));
+            if stage.is_finished() {
+                return actions;
             }
         }
+        panic!("stage never finished");
+    }
+
+    fn wave_starts(config: &MapConfig) -> Vec<(u32, usize, usize)> {
+        run(config).into_iter().filter_map(|(tick, action)| match action {
+            StageAction::WaveStarted { queue, wave, .. } => Some((tick, queue, wave)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn queue_without_waves_finishes() {
+        let actions = run(&config(vec![(1f32, WaveTiming::Absolute, vec![])]));
+        assert_eq!(actions, vec![(4, StageAction::QueueStarted { queue: 0 }), (4, StageAction::AllWavesFinished)]);
+    }
+
+    #[test]
+    fn absolute_waits_count_from_battle_start() {
+        let config = config(vec![(1f32, WaveTiming::Absolute, vec![wave(2f32), wave(2f32), wave(3f32)])]);
+        assert_eq!(wave_starts(&config), vec![(8, 0, 0), (8, 0, 1), (12, 0, 2)]);
+    }
+
+    #[test]
+    fn relative_to_queue_waits_count_from_queue_start() {
+        let config = config(vec![(1f32, WaveTiming::RelativeToQueue, vec![wave(2f32), wave(2f32), wave(3f32)])]);
+        assert_eq!(wave_starts(&config), vec![(12, 0, 0), (12, 0, 1), (16, 0, 2)]);
+    }
+
+    #[test]
+    fn relative_to_previous_waits_count_from_previous_wave() {
+        let config = config(vec![(1f32, WaveTiming::RelativeToPreviousWave, vec![wave(2f32), wave(0f32), wave(3f32)])]);
+        assert_eq!(wave_starts(&config), vec![(12, 0, 0), (12, 0, 1), (24, 0, 2)]);
+    }
+
+    #[test]
+    fn timing_is_chosen_per_queue() {
+        let config = config(vec![
+            (1f32, WaveTiming::Absolute, vec![wave(2f32)]),
+            (1f32, WaveTiming::RelativeToQueue, vec![wave(2f32)]),
+        ]);
+        assert_eq!(wave_starts(&config), vec![(8, 0, 0), (12, 1, 0)]);
+    }
+
+    #[test]
+    fn waves_spawn_on_cool_down_and_rotate_roads() {
+        let spawns = run(&config(vec![(0f32, WaveTiming::Absolute, vec![wave(0f32)])])).into_iter().filter_map(|(tick, action)| match action {
+            StageAction::Spawn { road_idx, count, .. } => Some((tick, road_idx, count)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(spawns, vec![(1, 0, 2), (4, 1, 2)]);
+    }
 
-        if finish {
-            map_stage.working_queues.remove(i);
+    #[test]
+    fn preview_count_matches_the_spawned_units() {
+        for (spawn_cool_down, duration) in [(0.5f32, 1f32), (0f32, 1f32), (0.75f32, 3f32), (1f32, 0f32), (0.3f32, 2.2f32)] {
+            let config = config(vec![(0f32, WaveTiming::Absolute, vec![Wave { spawn_cool_down, duration, ..wave(0f32) }])]);
+            let preview = MapStage::create(&config, &DifficultyConfig::default()).upcoming_waves(STEP)[0].count;
+            let spawned = run(&config).into_iter().map(|(_, action)| match action {
+                StageAction::Spawn { count, .. } => count,
+                _ => 0,
+            }).sum::<u32>();
+            assert_eq!(preview, spawned, "cool down {} duration {}", spawn_cool_down, duration);
         }
-    };
+    }
 
-    //update wave
-    for queue in &mut map_stage.working_queues {
-        let mut ww = std::mem::take(&mut queue.working_waves);
-        for i in (0..ww.len()).rev() {
-            let wave = &mut ww[i];
-            wave.work_time += delta;
-
-            let wave_config = &config.wave_queues[queue.queue_idx].waves[wave.wave_idx];
-            if wave.spawn_cool_down <= 0f32 {
-                wave.spawn_cool_down = wave_config.spawn_cool_down;
-                //spawn
-                let path_2_road = &map_stage.path_2_road[wave_config.path_index as usize];
-                let road_idx = path_2_road.start_idx + wave.spawn_road_idx;
-                wave.spawn_road_idx = (wave.spawn_road_idx + 1) % path_2_road.count;
+    #[test]
+    fn all_finished_is_sent_once() {
+        let actions = run(&config(vec![(0f32, WaveTiming::Absolute, vec![wave(0f32)]), (1f32, WaveTiming::Absolute, vec![])]));
+        let count = actions.iter().filter(|(_, action)| *action == StageAction::AllWavesFinished).count();
+        assert_eq!(count, 1);
+    }
 
-                let road = &map_stage.roads[road_idx];
-                let first_point = rand_position(&road.points[0].pos, 0.25f32);
-                let monster_config = monster_table.index(wave_config.unit);
-
-                let gltf: Handle<Scene> = asset_server.load(&monster_config.asset);
-                for _ in 0..wave_config.per_spawn_count {
-                    let pos = rand_position(&first_point, 3f32);
-                    commands.spawn_bundle(
-                        (
-                            Transform::from_translation(pos),
-                            GlobalTransform::identity(),
-                            MoveWithMapPath {
-                                road_index: road_idx as usize,
-                                target_point_index: 1,
-                                speed: monster_config
-                                    .move_speed,
-                            },
-                            HitBounds::create(0.2f32),
-                            Attrs::load_from_config(&monster_config.attrs),
-                        )
-                    ).with_children(|parent| {
-                        parent.spawn_scene(gltf.clone());
-                    });
+    fn advance_to(stage: &mut MapStage, tick: u32) {
+        for _ in 0..tick {
+            stage.advance(STEP);
+        }
+    }
+
+    #[test]
+    fn calling_early_moves_the_rest_of_the_queue_up() {
+        let config = config(vec![(0f32, WaveTiming::RelativeToPreviousWave, vec![wave(0f32), wave(4f32), wave(2f32)])]);
+        let mut stage = MapStage::create(&config, &DifficultyConfig::default());
+        advance_to(&mut stage, 4);
+
+        let call = stage.call_next_wave(0).unwrap();
+        assert_eq!(call, EarlyWaveCall { wave: 1, seconds: 3f32, reward: 30 });
+        assert_eq!(stage.advance(STEP).into_iter().filter(|action| matches!(action, StageAction::WaveStarted { wave: 1, .. })).count(), 1);
+
+        //the third wave still waits its 2 seconds after the called one
+        let next = &stage.upcoming_waves(STEP)[0];
+        assert_eq!((next.wave, next.seconds_left), (2, 1.75f32));
+    }
+
+    #[test]
+    fn only_started_queues_can_call() {
+        let config = config(vec![(5f32, WaveTiming::Absolute, vec![wave(6f32)])]);
+        let mut stage = MapStage::create(&config, &DifficultyConfig::default());
+        advance_to(&mut stage, 1);
+        assert_eq!(stage.call_next_wave(0), None);
+        assert_eq!(stage.call_next_wave(1), None);
+    }
+
+    #[test]
+    fn preview_lists_waves_in_start_order() {
+        let config = config(vec![
+            (0f32, WaveTiming::RelativeToQueue, vec![wave(1f32), wave(3f32)]),
+            (1f32, WaveTiming::Absolute, vec![wave(0f32)]),
+        ]);
+        let mut stage = MapStage::create(&config, &DifficultyConfig::default());
+        advance_to(&mut stage, 2);
+
+        let preview = stage.upcoming_waves(STEP).into_iter()
+            .map(|wave| (wave.queue, wave.wave, wave.count, wave.seconds_left, wave.early_call_reward))
+            .collect::<Vec<_>>();
+        assert_eq!(preview, vec![(0, 0, 4, 0.5f32, 5), (1, 0, 4, 0.5f32, 0), (0, 1, 4, 2.5f32, 0)]);
+    }
+
+    fn endless_config() -> EndlessConfig {
+        EndlessConfig {
+            name: "test".to_string(),
+            first_wave: 0f32,
+            wave_interval: 30f32,
+            spawn_cool_down: 1f32,
+            per_spawn_count: 2,
+            early_call_reward: 0f32,
+            units: vec![EndlessUnitConfig { unit: StringId::Id(1), cost: 1f32, from_minute: 0f32 }],
+            curve: vec![
+                EndlessCurvePoint { minute: 0f32, budget: 4f32, attr_scale: 1f32 },
+                EndlessCurvePoint { minute: 1f32, budget: 8f32, attr_scale: 2f32 },
+            ],
+            paths: Vec::new(),
+            scaled_attrs: vec![StringId::Id(2)],
+        }
+    }
+
+    #[test]
+    fn endless_stage_keeps_generating_stronger_waves() {
+        let endless = endless_config();
+        let mut stage = MapStage::create_endless(&config(vec![]), &DifficultyConfig::default(), EndlessWaves::new(&endless, 1, 7));
+        assert_eq!(stage.scaled_attrs(), &[2]);
+
+        let mut scales = Vec::new();
+        for _ in 0..(10 * 60 * 4) {
+            for action in stage.advance(STEP) {
+                if let StageAction::Spawn { attr_scale, .. } = action {
+                    scales.push(attr_scale);
                 }
-            } else {
-                wave.spawn_cool_down -= delta;
             }
+            assert!(!stage.is_finished());
+            assert!(stage.upcoming_waves(STEP).len() >= ENDLESS_LOOKAHEAD);
+        }
 
-            if wave.work_time >= wave_config.duration {
-                ww.remove(i);
-            }
+        assert_eq!(scales.first(), Some(&1f32));
+        //the curve goes on past its last point, after 10 minutes the monsters are about 10 times as strong
+        assert!(*scales.last().unwrap() > 9f32);
+    }
+
+    #[test]
+    fn difficulty_scales_wave_spawns() {
+        let difficulty = DifficultyConfig { per_spawn_count: 1.5f32, spawn_cool_down: 0.5f32, ..Default::default() };
+        let mut stage = MapStage::create(&config(vec![(0f32, WaveTiming::Absolute, vec![wave(0f32)])]), &difficulty);
+        let spawns = (0..4).flat_map(|_| stage.advance(STEP)).filter_map(|action| match action {
+            StageAction::Spawn { count, .. } => Some(count),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(spawns, vec![3, 3]);
+    }
+
+    #[test]
+    fn restored_progress_runs_the_same() {
+        let endless = endless_config();
+        let create = || MapStage::create_endless(&config(vec![]), &DifficultyConfig::default(), EndlessWaves::new(&endless, 1, 7));
+        let mut stage = create();
+        advance_to(&mut stage, 1000);
+
+        //a stage fresh from the map picks up where the saved one was
+        let mut restored = create();
+        restored.restore(&stage.progress());
+        for _ in 0..1000 {
+            assert_eq!(stage.advance(STEP), restored.advance(STEP));
         }
+        assert_eq!(stage.upcoming_waves(STEP), restored.upcoming_waves(STEP));
+    }
 
-        queue.working_waves = ww;
+    #[test]
+    fn remaining_distance_counts_the_rest_of_the_road() {
+        let road = MapStageRoad {
+            points: [(0f32, 0f32), (10f32, 0f32), (10f32, 5f32)].iter().map(|(x, z)| MapStageRoadPoint { pos: Vec3::new(*x, 0f32, *z) }).collect(),
+        };
+        assert_eq!(road.remaining_distance(1, Vec3::new(4f32, 0f32, 0f32)), 11f32);
+        assert_eq!(road.remaining_distance(2, Vec3::new(10f32, 0f32, 1f32)), 4f32);
+        assert_eq!(road.remaining_distance(3, Vec3::new(10f32, 0f32, 5f32)), 0f32);
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,18 @@
-use std::collections::HashMap;
+//generated by hashtoolbin codegen, sid!("name") needs no regeneration
+#![allow(non_upper_case_globals)]
 
-pub const fire:u64 = 10667177933177231896;
-pub const cross_bullet:u64 = 1629168651701161850;
-pub const goblin:u64 = 4706289301106383702;
-pub const hp_max:u64 = 16041127461211680921;
-pub const hp:u64 = 2913007616199000695;
-pub const cross_bullet_hurt:u64 = 9146520341250634384;
-pub const main:u64 = 5322843316632772325;
-pub const speed:u64 = 5411191639289302350;
+pub const beam_hurt: u64 = hashtoollib::const_hash("beam_hurt");
+pub const cross_bullet: u64 = hashtoollib::const_hash("cross_bullet");
+pub const cross_bullet_hurt: u64 = hashtoollib::const_hash("cross_bullet_hurt");
+pub const easy: u64 = hashtoollib::const_hash("easy");
+pub const endless: u64 = hashtoollib::const_hash("endless");
+pub const fire: u64 = hashtoollib::const_hash("fire");
+pub const goblin: u64 = hashtoollib::const_hash("goblin");
+pub const hard: u64 = hashtoollib::const_hash("hard");
+pub const hp: u64 = hashtoollib::const_hash("hp");
+pub const hp_max: u64 = hashtoollib::const_hash("hp_max");
+pub const main: u64 = hashtoollib::const_hash("main");
+pub const main_beam: u64 = hashtoollib::const_hash("main_beam");
+pub const main_rapid: u64 = hashtoollib::const_hash("main_rapid");
+pub const normal: u64 = hashtoollib::const_hash("normal");
+pub const speed: u64 = hashtoollib::const_hash("speed");
@@ -1,59 +1,387 @@
-use bevy::utils::HashMap;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use bevy::asset::{AssetLoader, HandleId, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::{TypeUuid, Uuid};
+use bevy::utils::{BoxedFuture, HashMap, HashSet};
+use crossbeam_queue::SegQueue;
+use crate::table_inherit;
+use crate::validate::ValidationReport;
 
-pub trait TableDataItem: serde::de::DeserializeOwned + Send + Sync + 'static {
+#[derive(Debug)]
+pub enum TableError {
+    Io { path: String, message: String },
+    Parse { table: &'static str, path: String, position: ron::de::Position, message: String },
+    MissingKey { table: &'static str, id: u64, name: String },
+    UnconvertedStringId { value: String },
+    StringIdNotStr { id: u64 },
+    Bake { path: String, message: String },
+    HashCollision { path: String, collision: hashtoollib::HashCollision },
+    MissingBase { table: &'static str, path: String, entry: String, base: String },
+    InheritCycle { table: &'static str, path: String, chain: Vec<String> },
+}
+
+impl Display for TableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableError::Io { path, message } => {
+                write!(f, "failed to load table config {}, error: {}", path, message)
+            }
+            TableError::Parse { table, path, position, message } => {
+                write!(f, "failed to load table {} [{}:{}], error: {}", table, path, position, message)
+            }
+            TableError::MissingKey { table, name, .. } => {
+                write!(f, "failed to find '{}' from table {}", name, table)
+            }
+            TableError::UnconvertedStringId { value } => {
+                write!(f, "the string {} id not convert into id yet", value)
+            }
+            TableError::StringIdNotStr { id } => {
+                write!(f, "the string {} has already convert into id", id)
+            }
+            TableError::Bake { path, message } => {
+                write!(f, "failed to bake table {}, error: {}", path, message)
+            }
+            TableError::HashCollision { path, collision } => {
+                write!(f, "hash collision while loading table {}: {}", path, collision)
+            }
+            TableError::MissingBase { table, path, entry, base } => {
+                write!(f, "entry '{}' of table {} [{}] inherits missing base '{}'", entry, table, path, base)
+            }
+            TableError::InheritCycle { table, path, chain } => {
+                write!(f, "table {} [{}] has an inherit cycle: {}", table, path, chain.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+pub trait TableDataItem: serde::de::DeserializeOwned + serde::Serialize + Send + Sync + 'static {
     fn get_name(&self) -> &str;
     fn parse(&mut self) {}
 }
 
 pub struct TableData<T> {
     dict: HashMap<u64, T>,
+    names: HashMap<u64, String>,
+    //(kept name, overwritten name) for every entry whose id was already taken
+    conflicts: Vec<(String, String)>,
+    path: String,
+    //hash of the .ron source, a replay made from other table content will not match
+    source_hash: u64,
+}
+
+//bump when the baked layout changes, config struct changes need a fresh bake as well
+const BAKE_VERSION: u32 = 2;
+
+//parsed entries keyed by their hashed name, so loading skips ron parsing and string hashing
+//the names come along for reports and error messages, release builds have no reverse dictionary
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BakedTable<E> {
+    version: u32,
+    source_hash: u64,
+    entries: Vec<(u64, E)>,
+    names: Vec<(u64, String)>,
+    conflicts: Vec<(String, String)>,
+}
+
+//release builds prefer "battle.attacker.bin" baked by hashtoolbin next to "battle.attacker.ron"
+pub fn baked_path(path: &str) -> String {
+    format!("{}.bin", path.strip_suffix(".ron").unwrap_or(path))
+}
+
+//every table shares the uuid of its item type, so TableData<T> can live in the asset server
+impl<T> TypeUuid for TableData<T> where T: TypeUuid {
+    const TYPE_UUID: Uuid = T::TYPE_UUID;
+}
+
+//sent after a table resource is swapped by the hot reload, entities built from the old data can refresh on it
+pub struct TableReloaded<T> {
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for TableReloaded<T> {
+    fn default() -> Self {
+        Self { _marker: PhantomData }
+    }
 }
 
 impl<T> TableData<T> where T: TableDataItem {
-    pub fn load_from_bytes(data: &[u8]) -> Self {
-        let list: Vec<T> = match ron::de::from_bytes(data) {
-            Ok(x) => x,
-            Err(e) => {
-                panic!("failed to load table {}, error: {}", std::any::type_name::<T>(), e);
+    //path is only kept for error messages and reports
+    pub fn try_load_from_bytes(data: &[u8], path: &str) -> Result<Self, TableError> {
+        let table_name = std::any::type_name::<T>();
+        let list: Vec<T> = match table_inherit::expand_bases(data, table_name, path)? {
+            None => {
+                ron::de::from_bytes(data).map_err(|e| TableError::Parse {
+                    table: table_name,
+                    path: path.to_string(),
+                    position: e.position,
+                    message: e.code.to_string(),
+                })?
             }
+            Some(entries) => {
+                entries.into_iter().map(|entry| {
+                    ron::de::from_str(&entry.text).map_err(|e| TableError::Parse {
+                        table: table_name,
+                        path: path.to_string(),
+                        position: entry.position,
+                        message: format!("entry '{}': {}", entry.name, e.code),
+                    })
+                }).collect::<Result<_, _>>()?
+            }
+        };
+
+        let mut table = Self {
+            dict: Default::default(),
+            names: Default::default(),
+            conflicts: Default::default(),
+            path: path.to_string(),
+            source_hash: hashtoollib::hash_bytes(data),
         };
 
-        let dict = list.into_iter().map(|mut item| {
-            let name = item.get_name();
-            let id = hashtoollib::hash(name);
+        for mut item in list {
+            let name = item.get_name().to_string();
+            let id = hashtoollib::hash(&name);
             item.parse();
-            (id, item)
-        }).collect::<HashMap<_, _>>();
+            if let Some(old) = table.names.insert(id, name.clone()) {
+                table.conflicts.push((name, old));
+            }
+            table.dict.insert(id, item);
+        }
+
+        //the dictionary keeps the collisions of every load so far, only those on a name of this table fail it
+        if hashtoollib::is_strict() {
+            let own = hashtoollib::collisions().into_iter().find(|collision| {
+                matches!(table.names.get(&collision.id), Some(name) if *name == collision.first || *name == collision.second)
+            });
+            if let Some(collision) = own {
+                return Err(TableError::HashCollision { path: path.to_string(), collision });
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn try_load_from_file(path: &str) -> Result<Self, TableError> {
+        let data = std::fs::read(path).map_err(|e| TableError::Io { path: path.to_string(), message: e.to_string() })?;
+        Self::try_load_from_bytes(&data, path)
+    }
+
+    //None when the bake is broken or was made from another version of the source
+    pub fn try_load_from_baked(baked: &[u8], source: &[u8], path: &str) -> Option<Self> {
+        let baked: BakedTable<T> = bincode::deserialize(baked).ok()?;
+        if baked.version != BAKE_VERSION || baked.source_hash != hashtoollib::hash_bytes(source) {
+            return None;
+        }
+
+        Some(Self {
+            dict: baked.entries.into_iter().collect(),
+            names: baked.names.into_iter().collect(),
+            conflicts: baked.conflicts,
+            path: path.to_string(),
+            source_hash: baked.source_hash,
+        })
+    }
+
+    pub fn save_baked(&self) -> Result<(), TableError> {
+        let bake_error = |message: String| TableError::Bake { path: self.path.clone(), message };
+
+        let source = std::fs::read(&self.path).map_err(|e| bake_error(e.to_string()))?;
+        let data = self.bake(&source).map_err(|e| bake_error(e.to_string()))?;
+        std::fs::write(baked_path(&self.path), data).map_err(|e| bake_error(e.to_string()))
+    }
+
+    fn bake(&self, source: &[u8]) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(&BakedTable {
+            version: BAKE_VERSION,
+            source_hash: hashtoollib::hash_bytes(source),
+            entries: self.iter().collect::<Vec<_>>(),
+            names: self.names.iter().map(|(id, name)| (*id, name.clone())).collect(),
+            conflicts: self.conflicts.clone(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn source_hash(&self) -> u64 {
+        self.source_hash
+    }
+
+    pub fn name_of(&self, id: u64) -> Option<&str> {
+        self.names.get(&id).map(|s| s.as_str())
+    }
 
-        Self { dict }
+    pub fn conflicts(&self) -> &[(String, String)] {
+        &self.conflicts
     }
 
-    pub fn load_from_file(path: &str) -> Self {
-        let data = std::fs::read(path).expect(format!("failed to load table config {}", path).as_str());
-        Self::load_from_bytes(&data)
+    pub fn iter(&self) -> impl Iterator<Item=(u64, &T)> {
+        self.dict.iter().map(|(id, item)| (*id, item))
     }
 
     pub fn find(&self, name: u64) -> Option<&T> {
         self.dict.get(&name)
     }
 
+    pub fn try_index(&self, name: u64) -> Result<&T, TableError> {
+        self.find(name).ok_or_else(|| TableError::MissingKey {
+            table: std::any::type_name::<T>(),
+            id: name,
+            name: crate::id_to_name(name),
+        })
+    }
+
     pub fn index(&self, name: u64) -> &T {
-        match self.find(name) {
-            None => {
-                let mut print_name = name.to_string();
+        match self.try_index(name) {
+            Ok(t) => t,
+            Err(e) => {
+                panic!("{}", e);
+            }
+        }
+    }
+}
+
+//all tables are .ron files, so each loader claims the full extension of its file, e.g. "attacker.ron" for "battle.attacker.ron"
+//the asset server only logs a failed load, so the loader also hands its error to TableHandles
+pub struct TableAssetLoader<T> {
+    extensions: [&'static str; 1],
+    errors: Arc<SegQueue<(String, TableError)>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> TableAssetLoader<T> {
+    pub fn create(path: &'static str, handles: &TableHandles) -> Self {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        let extension = file_name.split_once('.').map_or(file_name, |(_, ext)| ext);
+        Self { extensions: [extension], errors: handles.errors.clone(), _marker: PhantomData }
+    }
+}
+
+impl<T> AssetLoader for TableAssetLoader<T> where T: TableDataItem + TypeUuid {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().to_string_lossy().to_string();
 
-                #[cfg(feature = "debug")]
-                    {
-                        if let Some(s) = hashtoollib::un_hash(name) {
-                            print_name = s;
+            #[cfg(not(feature = "debug"))]
+                {
+                    if let Ok(baked) = load_context.read_asset_bytes(baked_path(&path)).await {
+                        match TableData::<T>::try_load_from_baked(&baked, bytes, &path) {
+                            Some(table) => {
+                                load_context.set_default_asset(LoadedAsset::new(table));
+                                return Ok(());
+                            }
+                            None => {
+                                warn!("baked table of {} is out of date, parse the source instead", path);
+                            }
                         }
                     }
+                }
 
-                panic!("failed to find '{}' from table {}", print_name, std::any::type_name::<T>());
-            }
-            Some(t) => {
-                t
+            let table = match TableData::<T>::try_load_from_bytes(bytes, &path) {
+                Ok(table) => table,
+                Err(e) => {
+                    let error = anyhow::anyhow!("{}", e);
+                    self.errors.push((path, e));
+                    return Err(error);
+                }
+            };
+            load_context.set_default_asset(LoadedAsset::new(table));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &self.extensions
+    }
+}
+
+//keeps the table handles alive for hot reload and tracks the tables not moved into resources yet
+#[derive(Default)]
+pub struct TableHandles {
+    paths: Vec<&'static str>,
+    handles: Vec<HandleUntyped>,
+    pending: HashSet<HandleId>,
+    errors: Arc<SegQueue<(String, TableError)>>,
+    //the error of the last failed load of each path
+    load_errors: HashMap<String, TableError>,
+}
+
+impl TableHandles {
+    pub fn register(&mut self, path: &'static str) {
+        self.paths.push(path);
+    }
+
+    pub fn start_load(&mut self, asset_server: &AssetServer) {
+        self.handles = self.paths.iter().map(|path| asset_server.load_untyped(*path)).collect();
+        self.pending = self.handles.iter().map(|h| h.id).collect();
+    }
+
+    pub fn is_all_loaded(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    //the tables the asset server gave up on, they stay pending until a fixed file is reloaded
+    pub fn failed(&mut self, asset_server: &AssetServer) -> Vec<(&'static str, String)> {
+        //the loader queues its error before the state turns Failed, so the states are read first
+        let failed = self.paths.iter().zip(&self.handles)
+            .filter(|(_, handle)| asset_server.get_load_state(handle.id) == LoadState::Failed)
+            .map(|(path, _)| *path)
+            .collect::<Vec<_>>();
+        while let Some((path, error)) = self.errors.pop() {
+            self.load_errors.insert(path, error);
+        }
+        failed.into_iter().map(|path| {
+            let message = self.load_errors.get(path).map_or_else(|| "failed to load".to_string(), |e| e.to_string());
+            (path, message)
+        }).collect()
+    }
+}
+
+//moves a loaded table out of Assets into the TableData<T> resource, a later load of the same file is a hot reload
+pub fn install_table_system<T>(mut commands: Commands,
+                               mut events: EventReader<AssetEvent<TableData<T>>>,
+                               mut assets: ResMut<Assets<TableData<T>>>,
+                               mut handles: ResMut<TableHandles>,
+                               current: Option<Res<TableData<T>>>,
+                               mut reloaded: EventWriter<TableReloaded<T>>) where T: TableDataItem + TypeUuid {
+    for event in events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        if let Some(table) = assets.remove(handle) {
+            if current.is_some() {
+                info!("table {} reloaded", table.path());
+                reloaded.send(TableReloaded::default());
             }
+            handles.pending.remove(&handle.id);
+            //a changed table has to be validated again before a battle starts with it
+            commands.remove_resource::<ValidationReport>();
+            commands.insert_resource(table);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::DifficultyConfig;
+
+    #[test]
+    fn baked_tables_keep_their_names() {
+        let source = br#"[(name: "easy", move_speed: 0.5), (name: "easy"), (name: "hard")]"#;
+        let table = TableData::<DifficultyConfig>::try_load_from_bytes(source, "difficulty").unwrap();
+        let baked = TableData::<DifficultyConfig>::try_load_from_baked(&table.bake(source).unwrap(), source, "difficulty").unwrap();
+
+        let hard = hashtoollib::hash("hard");
+        assert_eq!(baked.name_of(hard), Some("hard"));
+        assert_eq!(baked.conflicts(), table.conflicts());
+        assert_eq!(baked.conflicts().len(), 1);
+        assert!(TableData::<DifficultyConfig>::try_load_from_baked(&table.bake(source).unwrap(), b"[]", "difficulty").is_none());
+    }
+}
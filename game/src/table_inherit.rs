@@ -0,0 +1,534 @@
+use std::fmt::{Display, Formatter, Write};
+use ron::de::Position;
+use crate::table::TableError;
+
+//table entries may name a parent with `base: "goblin"`, the parent is deep merged under the entry before it is deserialized:
+//structs merge field by field, lists of named structs (e.g. attrs) merge by name, anything else is replaced by the child,
+//so does an empty list, `attrs: []` drops the attrs of the base.
+//ron::Value drops enum variant names, so the entries are kept in this small tree that writes itself back as ron text
+const BASE_FIELD: &str = "base";
+const NAME_FIELD: &str = "name";
+
+#[derive(Clone)]
+enum Node {
+    Atom(String),
+    Struct(Option<String>, Vec<(String, Node)>),
+    Tuple(Option<String>, Vec<Node>),
+    List(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+}
+
+pub struct ExpandedEntry {
+    pub name: String,
+    pub position: Position,
+    pub text: String,
+}
+
+impl Node {
+    fn field(&self, key: &str) -> Option<&Node> {
+        match self {
+            Node::Struct(_, fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    //"goblin" and Str("goblin") both name the entry goblin
+    fn as_name(&self) -> Option<String> {
+        match self {
+            Node::Atom(s) if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') => Some(s[1..s.len() - 1].to_string()),
+            Node::Tuple(Some(ident), values) if ident == "Str" && values.len() == 1 => values[0].as_name(),
+            _ => None,
+        }
+    }
+
+    fn merge(base: &Node, child: Node) -> Node {
+        match (base, child) {
+            (Node::Struct(base_ident, base_fields), Node::Struct(ident, fields)) if ident.is_none() || ident == *base_ident => {
+                let mut merged = base_fields.clone();
+                for (key, value) in fields {
+                    match merged.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, old)) => {
+                            *old = Node::merge(old, value);
+                        }
+                        None => {
+                            merged.push((key, value));
+                        }
+                    }
+                }
+                Node::Struct(base_ident.clone(), merged)
+            }
+
+            (Node::Tuple(base_ident, base_values), Node::Tuple(ident, values)) if ident == *base_ident && values.len() == base_values.len() => {
+                let merged = base_values.iter().zip(values).map(|(b, c)| Node::merge(b, c)).collect();
+                Node::Tuple(ident, merged)
+            }
+
+            //an empty child list is written on purpose, it clears the list of the base
+            (Node::List(base_values), Node::List(values)) if !values.is_empty() && Node::is_named_list(base_values) && Node::is_named_list(&values) => {
+                let mut merged = base_values.clone();
+                for value in values {
+                    let name = value.list_name();
+                    match merged.iter_mut().find(|v| v.list_name() == name) {
+                        Some(old) => {
+                            *old = Node::merge(old, value);
+                        }
+                        None => {
+                            merged.push(value);
+                        }
+                    }
+                }
+                Node::List(merged)
+            }
+
+            (_, child) => child,
+        }
+    }
+
+    //the key a named list merges by, "hp" and Str("hp") are the same entry
+    fn list_name(&self) -> Option<String> {
+        let name = self.field(NAME_FIELD)?;
+        Some(name.as_name().unwrap_or_else(|| name.to_string()))
+    }
+
+    fn is_named_list(values: &[Node]) -> bool {
+        values.iter().all(|v| v.field(NAME_FIELD).is_some())
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        //no trailing commas, ron does not accept them inside Some(..)
+        fn items<I: Display>(f: &mut Formatter<'_>, items: impl Iterator<Item=I>) -> std::fmt::Result {
+            for (idx, item) in items.enumerate() {
+                if idx > 0 {
+                    f.write_char(',')?;
+                }
+                write!(f, "{}", item)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Node::Atom(s) => f.write_str(s),
+            Node::Struct(ident, fields) => {
+                f.write_str(ident.as_deref().unwrap_or_default())?;
+                f.write_char('(')?;
+                items(f, fields.iter().map(|(key, value)| format!("{}:{}", key, value)))?;
+                f.write_char(')')
+            }
+            Node::Tuple(ident, values) => {
+                f.write_str(ident.as_deref().unwrap_or_default())?;
+                f.write_char('(')?;
+                items(f, values.iter())?;
+                f.write_char(')')
+            }
+            Node::List(values) => {
+                f.write_char('[')?;
+                items(f, values.iter())?;
+                f.write_char(']')
+            }
+            Node::Map(pairs) => {
+                f.write_char('{')?;
+                items(f, pairs.iter().map(|(key, value)| format!("{}:{}", key, value)))?;
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn position(&self, cursor: usize) -> Position {
+        let before = &self.text[..cursor];
+        let line = before.matches('\n').count() + 1;
+        let col = cursor - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        Position { line, col }
+    }
+
+    fn error(&self, message: &str) -> (Position, String) {
+        (self.position(self.cursor), message.to_string())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.cursor).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_whitespace() => {
+                    self.cursor += 1;
+                }
+                Some(b'/') if self.bytes.get(self.cursor + 1) == Some(&b'/') => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.cursor += 1;
+                    }
+                }
+                Some(b'/') if self.bytes.get(self.cursor + 1) == Some(&b'*') => {
+                    match self.text[self.cursor + 2..].find("*/") {
+                        Some(end) => self.cursor += end + 4,
+                        None => self.cursor = self.bytes.len(),
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), (Position, String)> {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.cursor += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    //consumes a trailing comma, returns true when the closing bracket is reached
+    fn end_of_item(&mut self, close: u8) -> Result<bool, (Position, String)> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b',') => {
+                self.cursor += 1;
+                self.skip_ws();
+                Ok(self.consume(close))
+            }
+            Some(c) if c == close => {
+                self.cursor += 1;
+                Ok(true)
+            }
+            _ => Err(self.error(&format!("expected ',' or '{}'", close as char))),
+        }
+    }
+
+    fn consume(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.cursor += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let start = self.cursor;
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => {}
+            _ => return None,
+        }
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_') {
+            self.cursor += 1;
+        }
+        Some(&self.text[start..self.cursor])
+    }
+
+    fn value(&mut self) -> Result<Node, (Position, String)> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b'"') | Some(b'\'') => self.quoted(),
+            Some(b'(') => self.group(None),
+            Some(b'[') => self.list(),
+            Some(b'{') => self.map(),
+            Some(c) if c.is_ascii_digit() || c == b'+' || c == b'-' || c == b'.' => {
+                let start = self.cursor;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, b'+' | b'-' | b'.' | b'_')) {
+                    self.cursor += 1;
+                }
+                Ok(Node::Atom(self.text[start..self.cursor].to_string()))
+            }
+            _ => match self.identifier() {
+                Some(ident) => {
+                    self.skip_ws();
+                    if self.peek() == Some(b'(') {
+                        self.group(Some(ident.to_string()))
+                    } else {
+                        Ok(Node::Atom(ident.to_string()))
+                    }
+                }
+                None => Err(self.error("unexpected character")),
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Node, (Position, String)> {
+        let start = self.cursor;
+        let quote = self.bytes[start];
+        self.cursor += 1;
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'\\') => {
+                    //the escaped char may take several bytes, the cursor has to stay on a char boundary
+                    self.cursor += 1;
+                    match self.text[self.cursor..].chars().next() {
+                        Some(c) => self.cursor += c.len_utf8(),
+                        None => return Err(self.error("unterminated string")),
+                    }
+                }
+                Some(c) if c == quote => {
+                    self.cursor += 1;
+                    return Ok(Node::Atom(self.text[start..self.cursor].to_string()));
+                }
+                _ => self.cursor += 1,
+            }
+        }
+    }
+
+    //a struct when the first item is `key:`, a tuple otherwise
+    fn group(&mut self, ident: Option<String>) -> Result<Node, (Position, String)> {
+        self.expect(b'(')?;
+        self.skip_ws();
+        if self.consume(b')') {
+            return Ok(Node::Tuple(ident, Vec::new()));
+        }
+
+        let start = self.cursor;
+        let is_struct = self.identifier().is_some() && {
+            self.skip_ws();
+            self.peek() == Some(b':')
+        };
+        self.cursor = start;
+
+        if is_struct {
+            let mut fields = Vec::new();
+            loop {
+                self.skip_ws();
+                let key = self.identifier().ok_or_else(|| self.error("expected field name"))?.to_string();
+                self.expect(b':')?;
+                fields.push((key, self.value()?));
+                if self.end_of_item(b')')? {
+                    return Ok(Node::Struct(ident, fields));
+                }
+            }
+        } else {
+            let mut values = Vec::new();
+            loop {
+                values.push(self.value()?);
+                if self.end_of_item(b')')? {
+                    return Ok(Node::Tuple(ident, values));
+                }
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Node, (Position, String)> {
+        self.expect(b'[')?;
+        self.skip_ws();
+        let mut values = Vec::new();
+        if self.consume(b']') {
+            return Ok(Node::List(values));
+        }
+        loop {
+            values.push(self.value()?);
+            if self.end_of_item(b']')? {
+                return Ok(Node::List(values));
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<Node, (Position, String)> {
+        self.expect(b'{')?;
+        self.skip_ws();
+        let mut pairs = Vec::new();
+        if self.consume(b'}') {
+            return Ok(Node::Map(pairs));
+        }
+        loop {
+            let key = self.value()?;
+            self.expect(b':')?;
+            pairs.push((key, self.value()?));
+            if self.end_of_item(b'}')? {
+                return Ok(Node::Map(pairs));
+            }
+        }
+    }
+
+    //the top level list of table entries, with the position each entry starts at
+    fn entries(&mut self) -> Result<Vec<(Position, Node)>, (Position, String)> {
+        self.expect(b'[')?;
+        self.skip_ws();
+        let mut entries = Vec::new();
+        if !self.consume(b']') {
+            loop {
+                self.skip_ws();
+                let position = self.position(self.cursor);
+                entries.push((position, self.value()?));
+                if self.end_of_item(b']')? {
+                    break;
+                }
+            }
+        }
+        self.skip_ws();
+        if self.peek().is_some() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(entries)
+    }
+}
+
+fn resolve(idx: usize,
+           entries: &[(Position, Node)],
+           names: &[Option<String>],
+           resolved: &mut Vec<Option<Node>>,
+           chain: &mut Vec<usize>,
+           table: &'static str,
+           path: &str) -> Result<(), TableError> {
+    if resolved[idx].is_some() {
+        return Ok(());
+    }
+
+    let entry_name = || names[idx].clone().unwrap_or_default();
+
+    if chain.contains(&idx) {
+        let mut cycle = chain.iter().map(|i| names[*i].clone().unwrap_or_default()).collect::<Vec<_>>();
+        cycle.push(entry_name());
+        return Err(TableError::InheritCycle { table, path: path.to_string(), chain: cycle });
+    }
+
+    let node = &entries[idx].1;
+    let base_name = match node.field(BASE_FIELD) {
+        None => {
+            resolved[idx] = Some(node.clone());
+            return Ok(());
+        }
+        Some(base) => base.as_name().unwrap_or_else(|| base.to_string()),
+    };
+
+    let base_idx = names.iter().position(|n| n.as_deref() == Some(base_name.as_str()))
+        .ok_or_else(|| TableError::MissingBase { table, path: path.to_string(), entry: entry_name(), base: base_name.clone() })?;
+
+    chain.push(idx);
+    resolve(base_idx, entries, names, resolved, chain, table, path)?;
+    chain.pop();
+
+    let mut child = node.clone();
+    if let Node::Struct(_, fields) = &mut child {
+        fields.retain(|(k, _)| k != BASE_FIELD);
+    }
+    let merged = Node::merge(resolved[base_idx].as_ref().unwrap(), child);
+    resolved[idx] = Some(merged);
+    Ok(())
+}
+
+//returns None when no entry has a base, the file is then deserialized directly so errors keep their exact position
+pub fn expand_bases(data: &[u8], table: &'static str, path: &str) -> Result<Option<Vec<ExpandedEntry>>, TableError> {
+    let parse_error = |(position, message): (Position, String)| TableError::Parse { table, path: path.to_string(), position, message };
+
+    let text = std::str::from_utf8(data).map_err(|e| parse_error((Position { line: 1, col: 1 }, e.to_string())))?;
+    let mut parser = Parser { text, bytes: text.as_bytes(), cursor: 0 };
+    let entries = parser.entries().map_err(parse_error)?;
+
+    if entries.iter().all(|(_, node)| node.field(BASE_FIELD).is_none()) {
+        return Ok(None);
+    }
+
+    let names = entries.iter().map(|(_, node)| node.field(NAME_FIELD).and_then(|n| n.as_name())).collect::<Vec<_>>();
+    let mut resolved = vec![None; entries.len()];
+    for idx in 0..entries.len() {
+        resolve(idx, &entries, &names, &mut resolved, &mut Vec::new(), table, path)?;
+    }
+
+    let expanded = resolved.into_iter().zip(entries.iter()).zip(names).map(|((node, (position, _)), name)| {
+        ExpandedEntry {
+            name: name.unwrap_or_default(),
+            position: *position,
+            text: node.unwrap().to_string(),
+        }
+    }).collect();
+
+    Ok(Some(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Result<Option<Vec<ExpandedEntry>>, TableError> {
+        expand_bases(text.as_bytes(), "test", "test.ron")
+    }
+
+    fn expanded(text: &str) -> Vec<String> {
+        expand(text).unwrap().unwrap().into_iter().map(|e| e.text).collect()
+    }
+
+    #[test]
+    fn no_base_keeps_the_file() {
+        assert!(expand(r#"[(name: "a", hp: 1)]"#).unwrap().is_none());
+    }
+
+    #[test]
+    fn child_inherits_and_overrides() {
+        let entries = expanded(r#"[
+            (name: "a", hp: 10, speed: 2.5, kind: Shoot((cd: 1)), attrs: [(name: "hp", value: 1), (name: "mp", value: 2)], tags: [1, 2]),
+            (name: "b", base: "a", hp: 20, attrs: [(name: "mp", value: 5)], tags: []),
+        ]"#);
+
+        assert_eq!(entries[0], r#"(name:"a",hp:10,speed:2.5,kind:Shoot((cd:1)),attrs:[(name:"hp",value:1),(name:"mp",value:2)],tags:[1,2])"#);
+        assert_eq!(entries[1], r#"(name:"b",hp:20,speed:2.5,kind:Shoot((cd:1)),attrs:[(name:"hp",value:1),(name:"mp",value:5)],tags:[])"#);
+    }
+
+    #[test]
+    fn named_lists_match_names_in_any_form() {
+        let entries = expanded(r#"[
+            (name: "a", attrs: [(name: Str("hp"), value: 1), (name: "mp", value: 2)]),
+            (name: "b", base: "a", attrs: [(name: "hp", value: 3), (name: Str("mp"), value: 4)]),
+        ]"#);
+
+        assert_eq!(entries[1], r#"(name:"b",attrs:[(name:"hp",value:3),(name:Str("mp"),value:4)])"#);
+    }
+
+    #[test]
+    fn empty_list_clears_the_base_list() {
+        let entries = expanded(r#"[
+            (name: "a", attrs: [(name: "hp", value: 1)]),
+            (name: "b", base: "a", attrs: []),
+        ]"#);
+
+        assert_eq!(entries[1], r#"(name:"b",attrs:[])"#);
+    }
+
+    #[test]
+    fn chains_resolve_in_any_order() {
+        let entries = expanded(r#"[
+            (name: "c", base: Str("b"), speed: 3),
+            (name: "b", base: "a", hp: 2),
+            (name: "a", hp: 1, speed: 1, cost: 5),
+        ]"#);
+
+        assert_eq!(entries[0], r#"(name:"c",hp:2,speed:3,cost:5)"#);
+        assert_eq!(entries[1], r#"(name:"b",hp:2,speed:1,cost:5)"#);
+    }
+
+    #[test]
+    fn cycle_is_reported() {
+        match expand(r#"[(name: "a", base: "b"), (name: "b", base: "a")]"#) {
+            Err(TableError::InheritCycle { chain, .. }) => {
+                assert_eq!(chain, vec!["a", "b", "a"]);
+            }
+            _ => panic!("expected an inherit cycle"),
+        }
+    }
+
+    #[test]
+    fn missing_base_is_reported() {
+        match expand(r#"[(name: "a", base: "nope")]"#) {
+            Err(TableError::MissingBase { entry, base, .. }) => {
+                assert_eq!((entry.as_str(), base.as_str()), ("a", "nope"));
+            }
+            _ => panic!("expected a missing base"),
+        }
+    }
+
+    #[test]
+    fn truncated_string_is_an_error() {
+        for text in [r#"[(name: "a\"#, r#"[(name: "a"#, "[(name: \"\\\u{e9}"] {
+            assert!(matches!(expand(text), Err(TableError::Parse { .. })), "{} should not parse", text);
+        }
+    }
+}
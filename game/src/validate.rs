@@ -0,0 +1,442 @@
+use std::fmt::{Display, Formatter};
+use bevy::asset::{AssetServerSettings, FileAssetIo, LoadState};
+use crate::attacker::{AbilityConfig, AttackerConfig};
+use crate::attrs::AttrsConfig;
+use crate::difficulty::DifficultyConfig;
+use crate::effect::EffectsConfig;
+use crate::endless::EndlessConfig;
+use crate::force::ForceConfig;
+use crate::game::MAP_PATH;
+use crate::map::MapConfigAsset;
+use crate::monster::MonsterConfig;
+use crate::prelude::*;
+use crate::proto::PathEditor::MapConfig;
+use crate::table::{TableData, TableDataItem, TableHandles};
+
+#[derive(PartialEq)]
+pub enum ValidationIssue {
+    LoadFailed { file: String, message: String },
+    DuplicateName { file: String, name: String },
+    HashCollision { file: String, name: String, other: String, id: u64 },
+    UnresolvedReference { file: String, entry: String, field: String, target: &'static str, id: u64 },
+    OutOfRange { file: String, entry: String, field: String, index: usize, len: usize },
+    MissingNode { file: String, entry: String, field: String, asset: String, node: u64 },
+    UnreadableAsset { file: String, entry: String, asset: String },
+    MissingAsset { file: String, entry: String, asset: String },
+    EmptyList { file: String, entry: String, field: String },
+    NotPositive { file: String, entry: String, field: String, value: f32 },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::LoadFailed { file, message } => {
+                write!(f, "[{}] {}", file, message)
+            }
+            ValidationIssue::DuplicateName { file, name } => {
+                write!(f, "[{}] duplicate entry '{}'", file, name)
+            }
+            ValidationIssue::HashCollision { file, name, other, id } => {
+                write!(f, "[{}] entry '{}' and '{}' collide on id {}", file, name, other, id)
+            }
+            ValidationIssue::UnresolvedReference { file, entry, field, target, id } => {
+                write!(f, "[{}] entry '{}' field '{}' refers to missing '{}' in {}", file, entry, field, crate::id_to_name(*id), target)
+            }
+            ValidationIssue::OutOfRange { file, entry, field, index, len } => {
+                write!(f, "[{}] entry '{}' field '{}' is {} but only {} exist", file, entry, field, index, len)
+            }
+            ValidationIssue::MissingNode { file, entry, field, asset, node } => {
+                write!(f, "[{}] entry '{}' field '{}' names node '{}' that {} does not have", file, entry, field, crate::id_to_name(*node), asset)
+            }
+            ValidationIssue::UnreadableAsset { file, entry, asset } => {
+                write!(f, "[{}] entry '{}' uses {} which is no gltf model", file, entry, asset)
+            }
+            ValidationIssue::MissingAsset { file, entry, asset } => {
+                write!(f, "[{}] entry '{}' uses {} which does not exist", file, entry, asset)
+            }
+            ValidationIssue::EmptyList { file, entry, field } => {
+                write!(f, "[{}] entry '{}' field '{}' is empty", file, entry, field)
+            }
+            ValidationIssue::NotPositive { file, entry, field, value } => {
+                write!(f, "[{}] entry '{}' field '{}' is {} but has to be above 0", file, entry, field, value)
+            }
+        }
+    }
+}
+
+//collects every broken table or map entry instead of stopping at the first one
+#[derive(Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} config issue(s) found:", self.issues.len())?;
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn check_names<T>(&mut self, table: &TableData<T>) where T: TableDataItem {
+        for (name, other) in table.conflicts() {
+            if name == other {
+                self.issues.push(ValidationIssue::DuplicateName { file: table.path().to_string(), name: name.clone() });
+            } else {
+                self.issues.push(ValidationIssue::HashCollision {
+                    file: table.path().to_string(),
+                    name: name.clone(),
+                    other: other.clone(),
+                    id: hashtoollib::hash(name),
+                });
+            }
+        }
+    }
+
+    fn check_ref<T>(&mut self, file: &str, entry: &str, field: &str, target: &TableData<T>, id: u64) where T: TableDataItem {
+        if target.find(id).is_none() {
+            self.issues.push(ValidationIssue::UnresolvedReference {
+                file: file.to_string(),
+                entry: entry.to_string(),
+                field: field.to_string(),
+                target: std::any::type_name::<T>(),
+                id,
+            });
+        }
+    }
+
+    fn check_attrs(&mut self, file: &str, entry: &str, attrs: &AttrsConfig) {
+        for attr in &attrs.values {
+            if let Some(max) = &attr.max {
+                let max = max.id();
+                if attrs.values.iter().all(|v| v.name.id() != max) {
+                    self.issues.push(ValidationIssue::UnresolvedReference {
+                        file: file.to_string(),
+                        entry: entry.to_string(),
+                        field: format!("attrs.{}.max", crate::id_to_name(attr.name.id())),
+                        target: std::any::type_name::<AttrsConfig>(),
+                        id: max,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn validate_tables(&mut self,
+                           attacker: &TableData<AttackerConfig>,
+                           monster: &TableData<MonsterConfig>,
+                           effect: &TableData<EffectsConfig>,
+                           force: &TableData<ForceConfig>,
+                           endless: &TableData<EndlessConfig>,
+                           difficulty: &TableData<DifficultyConfig>) {
+        self.check_names(attacker);
+        self.check_names(monster);
+        self.check_names(effect);
+        self.check_names(force);
+        self.check_names(endless);
+        self.check_names(difficulty);
+
+        //the hash dictionary also sees collisions between tables and with ids named in code
+        fn holder<T>(table: &TableData<T>, id: u64) -> Option<&str> where T: TableDataItem {
+            table.name_of(id).map(|_| table.path())
+        }
+        for collision in hashtoollib::collisions() {
+            let reported = self.issues.iter().any(|issue| matches!(issue, ValidationIssue::HashCollision { id, .. } if *id == collision.id));
+            if reported {
+                continue;
+            }
+            let files = [holder(attacker, collision.id), holder(monster, collision.id), holder(effect, collision.id),
+                holder(force, collision.id), holder(endless, collision.id), holder(difficulty, collision.id)];
+            let files = files.iter().flatten().copied().collect::<Vec<_>>();
+            self.issues.push(ValidationIssue::HashCollision {
+                file: if files.is_empty() { "string ids".to_string() } else { files.join(", ") },
+                name: collision.first,
+                other: collision.second,
+                id: collision.id,
+            });
+        }
+
+        for (id, config) in monster.iter() {
+            let entry = monster.name_of(id).unwrap_or_default();
+            self.check_attrs(monster.path(), entry, &config.attrs);
+        }
+
+        for (id, config) in attacker.iter() {
+            let entry = attacker.name_of(id).unwrap_or_default();
+            for ability in &config.abilities {
+                match ability {
+                    AbilityConfig::Shoot(shoot) => {
+                        self.check_ref(attacker.path(), entry, "abilities.force", force, shoot.force.id());
+                    }
+                    AbilityConfig::Channel(channel) => {
+                        self.check_ref(attacker.path(), entry, "abilities.effect", effect, channel.effect.id());
+                        if channel.effect_interval.is_nan() || channel.effect_interval <= 0f32 {
+                            self.issues.push(ValidationIssue::NotPositive {
+                                file: attacker.path().to_string(),
+                                entry: entry.to_string(),
+                                field: "abilities.effect_interval".to_string(),
+                                value: channel.effect_interval,
+                            });
+                        }
+                    }
+                }
+            }
+            for upgrade in &config.upgrades {
+                self.check_ref(attacker.path(), entry, "upgrades.to", attacker, upgrade.to.id());
+            }
+        }
+
+        for (id, config) in force.iter() {
+            let entry = force.name_of(id).unwrap_or_default();
+            self.check_ref(force.path(), entry, "effect", effect, config.effect.id());
+        }
+
+        for (id, config) in endless.iter() {
+            let entry = endless.name_of(id).unwrap_or_default();
+            if config.units.is_empty() {
+                self.issues.push(ValidationIssue::EmptyList { file: endless.path().to_string(), entry: entry.to_string(), field: "units".to_string() });
+            }
+            for unit in &config.units {
+                self.check_ref(endless.path(), entry, "units.unit", monster, unit.unit.id());
+                //a wave is its budget divided by the unit cost
+                if unit.cost.is_nan() || unit.cost <= 0f32 {
+                    self.issues.push(ValidationIssue::NotPositive {
+                        file: endless.path().to_string(),
+                        entry: entry.to_string(),
+                        field: "units.cost".to_string(),
+                        value: unit.cost,
+                    });
+                }
+            }
+        }
+    }
+
+    //the fire nodes have to be in the tower model, `read` gives the bytes of an asset path or none if it is not there
+    pub fn validate_scenes(&mut self, attacker: &TableData<AttackerConfig>, read: impl Fn(&str) -> Option<Vec<u8>>) {
+        for (id, config) in attacker.iter() {
+            let entry = attacker.name_of(id).unwrap_or_default();
+            let asset = config.asset.split('#').next().unwrap_or_default();
+            let data = match read(asset) {
+                Some(data) => data,
+                None => {
+                    self.issues.push(ValidationIssue::MissingAsset { file: attacker.path().to_string(), entry: entry.to_string(), asset: asset.to_string() });
+                    continue;
+                }
+            };
+            //node names are no config names, they are hashed without landing in the reverse dictionary
+            let nodes = match gltf_node_names(&data) {
+                Some(nodes) => nodes.iter().map(|name| hashtoollib::const_hash(name)).collect::<Vec<_>>(),
+                None => {
+                    self.issues.push(ValidationIssue::UnreadableAsset { file: attacker.path().to_string(), entry: entry.to_string(), asset: asset.to_string() });
+                    continue;
+                }
+            };
+
+            for ability in &config.abilities {
+                let (field, node) = match ability {
+                    AbilityConfig::Shoot(shoot) => ("abilities.Shoot.fire_node", shoot.fire_node.id()),
+                    AbilityConfig::Channel(channel) => ("abilities.Channel.fire_node", channel.fire_node.id()),
+                };
+                if !nodes.contains(&node) {
+                    self.issues.push(ValidationIssue::MissingNode {
+                        file: attacker.path().to_string(),
+                        entry: entry.to_string(),
+                        field: field.to_string(),
+                        asset: asset.to_string(),
+                        node,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn validate_map(&mut self, file: &str, map: &MapConfig, monster: &TableData<MonsterConfig>, endless: &TableData<EndlessConfig>) {
+        for (queue_idx, queue) in map.wave_queues.iter().enumerate() {
+            for (wave_idx, wave) in queue.waves.iter().enumerate() {
+                let entry = format!("queue {} wave {}", queue_idx, wave_idx);
+                self.check_ref(file, &entry, "unit", monster, wave.unit);
+                if wave.path_index < 0 || wave.path_index as usize >= map.paths.len() {
+                    self.issues.push(ValidationIssue::OutOfRange {
+                        file: file.to_string(),
+                        entry,
+                        field: "path_index".to_string(),
+                        index: wave.path_index.max(0) as usize,
+                        len: map.paths.len(),
+                    });
+                }
+            }
+        }
+
+        //endless modes run on every map, so their paths have to exist on this one
+        for (id, config) in endless.iter() {
+            let entry = endless.name_of(id).unwrap_or_default();
+            if config.paths.is_empty() && map.paths.is_empty() {
+                self.issues.push(ValidationIssue::EmptyList { file: endless.path().to_string(), entry: entry.to_string(), field: "paths".to_string() });
+            }
+            for path in config.paths.iter().filter(|path| **path >= map.paths.len()) {
+                self.issues.push(ValidationIssue::OutOfRange {
+                    file: endless.path().to_string(),
+                    entry: entry.to_string(),
+                    field: "paths".to_string(),
+                    index: *path,
+                    len: map.paths.len(),
+                });
+            }
+        }
+    }
+}
+
+//a .glb model keeps its json in the first chunk after the 12 byte header, a .gltf model is the json
+fn gltf_json(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(b"glTF") {
+        return Some(data);
+    }
+    if data.get(16..20)? != b"JSON" {
+        return None;
+    }
+    let len = u32::from_le_bytes(data.get(12..16)?.try_into().ok()?) as usize;
+    data.get(20..20 + len)
+}
+
+//the names of the nodes in a gltf model, nodes without a name are left out
+fn gltf_node_names(data: &[u8]) -> Option<Vec<String>> {
+    let json: serde_json::Value = serde_json::from_slice(gltf_json(data)?).ok()?;
+    match json.get("nodes") {
+        Some(serde_json::Value::Array(nodes)) => Some(nodes.iter().filter_map(|node| node.get("name")?.as_str().map(str::to_string)).collect()),
+        None => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+//the fire nodes are only checked where the tower models are loaded, a headless battle never spawns them
+pub struct ValidateScenes;
+
+//runs in Loading until the tables and the map are loaded, only a clean report lets the battle start
+//a broken one is logged and kept for the editor to show, a reloaded table drops it and validates again
+pub fn validate_battle_system(world: &mut World) {
+    //a file that failed to load is all there is to report, the rest waits until it is fixed
+    let asset_server = world.get_resource::<AssetServer>().unwrap().clone();
+    let mut failed = world.get_resource_mut::<TableHandles>().unwrap().failed(&asset_server).into_iter()
+        .map(|(file, message)| ValidationIssue::LoadFailed { file: file.to_string(), message })
+        .collect::<Vec<_>>();
+    let map_handle = world.get_resource::<Handle<MapConfigAsset>>().unwrap().clone();
+    if asset_server.get_load_state(&map_handle) == LoadState::Failed {
+        failed.push(ValidationIssue::LoadFailed { file: MAP_PATH.to_string(), message: "failed to load the map config".to_string() });
+    }
+    if !failed.is_empty() {
+        if !matches!(world.get_resource::<ValidationReport>(), Some(report) if report.issues == failed) {
+            let report = ValidationReport { issues: failed };
+            error!("{}", report);
+            world.insert_resource(report);
+        }
+        return;
+    }
+    if matches!(world.get_resource::<ValidationReport>(), Some(report) if report.issues.iter().any(|issue| matches!(issue, ValidationIssue::LoadFailed { .. }))) {
+        world.remove_resource::<ValidationReport>();
+    }
+
+    if world.contains_resource::<ValidationReport>() || !world.get_resource::<TableHandles>().unwrap().is_all_loaded() {
+        return;
+    }
+    let map = match world.get_resource::<Assets<MapConfigAsset>>().unwrap().get(&map_handle) {
+        Some(map) => map,
+        None => return,
+    };
+    let (attacker, monster, effect, force, endless, difficulty) = match (world.get_resource::<TableData<AttackerConfig>>(),
+                                                                         world.get_resource::<TableData<MonsterConfig>>(),
+                                                                         world.get_resource::<TableData<EffectsConfig>>(),
+                                                                         world.get_resource::<TableData<ForceConfig>>(),
+                                                                         world.get_resource::<TableData<EndlessConfig>>(),
+                                                                         world.get_resource::<TableData<DifficultyConfig>>()) {
+        (Some(attacker), Some(monster), Some(effect), Some(force), Some(endless), Some(difficulty)) => (attacker, monster, effect, force, endless, difficulty),
+        _ => return,
+    };
+
+    let mut report = ValidationReport::default();
+    report.validate_tables(attacker, monster, effect, force, endless, difficulty);
+    if world.contains_resource::<ValidateScenes>() {
+        let asset_root = FileAssetIo::get_root_path().join(&world.get_resource::<AssetServerSettings>().unwrap().asset_folder);
+        report.validate_scenes(attacker, |asset| std::fs::read(asset_root.join(asset)).ok());
+    }
+    let file = asset_server.get_handle_path(&map_handle)
+        .map_or(MAP_PATH.to_string(), |p| p.path().display().to_string());
+    report.validate_map(&file, &map.config, monster, endless);
+
+    if !report.is_ok() {
+        error!("{}", report);
+    }
+    world.insert_resource(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fire_nodes_must_be_in_the_model() {
+        let attacker = TableData::<AttackerConfig>::try_load_from_bytes(br#"[
+            (name: "a", asset: "a.gltf#Scene0", abilities: [Shoot((cd:1, reload_time:1, magazine:1, fire_node:Str("fire"), range:1, force:Str("f")))]),
+            (name: "b", asset: "b.gltf", abilities: [Channel((total_value:1, value_cost_speed:1, fire_node:Str("muzzle"), range:1, effect:Str("e"), effect_interval:1, regen_speed:1))]),
+            (name: "c", asset: "missing.glb", abilities: [Shoot((cd:1, reload_time:1, magazine:1, fire_node:Str("fire"), range:1, force:Str("f")))]),
+        ]"#, "attackers").unwrap();
+        let read = |asset: &str| match asset {
+            "a.gltf" | "b.gltf" => Some(br#"{"asset": {"version": "2.0"}, "nodes": [{"name": "body", "mesh": 0}, {"children": [0]}, {"name": "fire", "translation": [0, 1.5e-1, -2]}]}"#.to_vec()),
+            _ => None,
+        };
+
+        let mut report = ValidationReport::default();
+        report.validate_scenes(&attacker, read);
+
+        assert_eq!(report.issues.len(), 2);
+        assert!(report.issues.iter().any(|issue| matches!(issue, ValidationIssue::MissingNode { entry, node, .. } if entry == "b" && *node == hashtoollib::hash("muzzle"))));
+        assert!(report.issues.iter().any(|issue| matches!(issue, ValidationIssue::MissingAsset { entry, asset, .. } if entry == "c" && asset == "missing.glb")));
+    }
+
+    #[test]
+    fn endless_modes_need_units_with_a_cost() {
+        let tables = |endless: &[u8]| (
+            TableData::<AttackerConfig>::try_load_from_bytes(b"[]", "attackers").unwrap(),
+            TableData::<MonsterConfig>::try_load_from_bytes(b"[]", "monsters").unwrap(),
+            TableData::<EffectsConfig>::try_load_from_bytes(b"[]", "effects").unwrap(),
+            TableData::<ForceConfig>::try_load_from_bytes(b"[]", "forces").unwrap(),
+            TableData::<EndlessConfig>::try_load_from_bytes(endless, "endless").unwrap(),
+            TableData::<DifficultyConfig>::try_load_from_bytes(b"[]", "difficulty").unwrap(),
+        );
+        let validate = |endless: &[u8]| {
+            let (attacker, monster, effect, force, endless, difficulty) = tables(endless);
+            let mut report = ValidationReport::default();
+            report.validate_tables(&attacker, &monster, &effect, &force, &endless, &difficulty);
+            report.issues
+        };
+
+        let issues = validate(br#"[(name: "none", first_wave: 1, wave_interval: 1, spawn_cool_down: 1, per_spawn_count: 1, units: [], curve: [])]"#);
+        assert!(matches!(issues.as_slice(), [ValidationIssue::EmptyList { field, .. }] if field == "units"));
+
+        let issues = validate(br#"[(name: "free", first_wave: 1, wave_interval: 1, spawn_cool_down: 1, per_spawn_count: 1, units: [(unit: Str("goblin"), cost: 0)], curve: [])]"#);
+        assert!(issues.iter().any(|issue| matches!(issue, ValidationIssue::NotPositive { field, .. } if field == "units.cost")));
+    }
+
+    #[test]
+    fn endless_modes_need_a_path_on_the_map() {
+        let monster = TableData::<MonsterConfig>::try_load_from_bytes(b"[]", "monsters").unwrap();
+        let endless = TableData::<EndlessConfig>::try_load_from_bytes(br#"[
+            (name: "any", first_wave: 1, wave_interval: 1, spawn_cool_down: 1, per_spawn_count: 1, units: [(unit: Str("goblin"), cost: 1)], curve: []),
+        ]"#, "endless").unwrap();
+
+        let mut report = ValidationReport::default();
+        report.validate_map("map", &MapConfig::default(), &monster, &endless);
+        assert!(matches!(report.issues.as_slice(), [ValidationIssue::EmptyList { entry, field, .. }] if entry == "any" && field == "paths"));
+
+        let map = MapConfig { paths: vec![Default::default()], ..Default::default() };
+        let mut report = ValidationReport::default();
+        report.validate_map("map", &map, &monster, &endless);
+        assert!(report.is_ok());
+    }
+}
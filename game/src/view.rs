@@ -0,0 +1,150 @@
+use std::ops::Deref;
+use crate::game::GameState;
+use crate::node;
+use crate::node::{HierarchyNameMap, HierarchyNameMapInitTag};
+use crate::map::MapConfigAsset;
+use crate::prelude::*;
+
+//gltf scene shown under the entity, only spawned when the game runs with a window
+#[derive(Component)]
+pub struct SceneView {
+    pub asset: String,
+}
+
+//the scene of a SceneView hangs under this child, so another asset can take the old scene away
+#[derive(Component)]
+struct SceneViewRoot {}
+
+pub struct ViewPlugin;
+
+impl Plugin for ViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_map_view_system))
+            .add_system_set(SystemSet::on_update(GameState::Playing)
+                .with_system(spawn_scene_view_system)
+                .with_system(node::init_node_name_system));
+    }
+}
+
+fn spawn_scene_view_system(mut commands: Commands,
+                           query: Query<(Entity, &SceneView, Option<&Children>, Option<&HierarchyNameMap>), Changed<SceneView>>,
+                           roots: Query<(), With<SceneViewRoot>>,
+                           asset_server: Res<AssetServer>) {
+    for (entity, view, children, names) in query.iter() {
+        //an upgraded tower changes its asset, the nodes of the old scene are looked up again in the new one
+        for child in children.iter().flat_map(|children| children.iter()).filter(|child| roots.get(**child).is_ok()) {
+            commands.entity(*child).despawn_recursive();
+            if names.is_some() {
+                commands.entity(entity).remove::<HierarchyNameMapInitTag>().insert(HierarchyNameMap::create());
+            }
+        }
+
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn_bundle((Transform::identity(), GlobalTransform::identity(), SceneViewRoot {})).with_children(|root| {
+                root.spawn_scene(asset_server.load(view.asset.as_str()));
+            });
+        });
+    }
+}
+
+fn spawn_map_view_system(mut commands: Commands,
+                         map: Res<Assets<MapConfigAsset>>,
+                         map_handle: Res<Handle<MapConfigAsset>>,
+                         asset_server: Res<AssetServer>,
+                         mut meshes: ResMut<Assets<Mesh>>,
+                         mut materials: ResMut<Assets<StandardMaterial>>) {
+    let config = &map.get(map_handle.deref()).expect("no map config loaded").config;
+
+    commands.spawn_bundle((
+        Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, 0.0f32, 180.0f32.to_radians(), 0.0f32)),
+        GlobalTransform::identity()
+    )).with_children(|parent| {
+        parent.spawn_scene(asset_server.load("gltf/Map_export.glb#Scene0"));
+    });
+
+    //camera
+    {
+        let c = config.camera.as_ref().unwrap();
+        let pos = c.position.as_ref().unwrap();
+
+        let rot = c.rotation.as_ref().unwrap();
+        let transform = Transform::from_matrix(
+            Mat4::from_scale_rotation_translation(Vec3::ONE,
+                                                  Quat::from_xyzw(rot.x, rot.y, rot.z, rot.w),
+                                                  Vec3::new(pos.x, pos.y, pos.z)));
+
+        commands.spawn_bundle(PerspectiveCameraBundle {
+            transform,
+            ..Default::default()
+        });
+
+        //let look_at = c.look_at.as_ref().unwrap();
+        // commands.spawn_bundle(OrbitCameraBundle::new(
+        //     OrbitCameraController::default(),
+        //     PerspectiveCameraBundle::default(),
+        //     Vec3::new(pos.x, pos.y, pos.z),
+        //     Vec3::new(look_at.x, look_at.y, look_at.z),
+        // ));
+    }
+
+    //light
+    //if false
+    {
+        const HALF_SIZE: f32 = 1.0;
+        let light_config = config.light.as_ref().unwrap();
+        let color = light_config.color.as_ref().unwrap();
+        let pos = light_config.position.as_ref().unwrap();
+        // let look_at = light_config.look_at.as_ref().unwrap();
+        // let transform = Transform::from_xyz(pos.x, pos.y, pos.z).looking_at(Vec3::new(look_at.x, look_at.y, look_at.z), Vec3::Y);
+        let rot = light_config.rotation.as_ref().unwrap();
+        let transform = Transform::from_matrix(
+            Mat4::from_scale_rotation_translation(Vec3::ONE,
+                                                  Quat::from_xyzw(rot.x, rot.y, rot.z, rot.w),
+                                                  Vec3::new(pos.x, pos.y, pos.z)));
+
+        commands.spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadow_projection: OrthographicProjection {
+                    left: -HALF_SIZE,
+                    right: HALF_SIZE,
+                    bottom: -HALF_SIZE,
+                    top: HALF_SIZE,
+                    near: -10.0 * HALF_SIZE,
+                    far: 10.0 * HALF_SIZE,
+                    ..Default::default()
+                },
+                shadow_depth_bias: light_config.shadow_bias,
+                shadow_normal_bias: light_config.shadow_normal_bias,
+                shadows_enabled: true,
+                color: Color::rgb(color.x, color.y, color.z),
+                ..Default::default()
+            },
+            transform,
+            ..Default::default()
+        });
+    }
+
+    //path point
+    {
+        let ps = &config.paths;
+
+        let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
+        let material = materials.add(StandardMaterial {
+            base_color: Color::GREEN,
+            ..Default::default()
+        });
+
+        for path in ps {
+            for point in &path.points {
+                let pos = point.position.as_ref().unwrap();
+
+                commands.spawn_bundle(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(pos.x, pos.y + 0.5f32, pos.z),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+}
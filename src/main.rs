@@ -1,11 +1,46 @@
 #[allow(unused_variables, dead_code)]
 use editor::EditorPlugin;
-use game::GamePlugin;
+use game::{DifficultyMode, EndlessMode, GamePlugin, ReplayMode, ReplayPlugin, SaveMode, SavePlugin};
 use game::prelude::*;
 
+//the value after a flag, e.g. "a.ron" of "--record a.ron"
+fn flag_value(flag: &str) -> Option<String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let idx = args.iter().position(|arg| arg == flag)?;
+    Some(args.get(idx + 1).expect(format!("{} needs a value", flag).as_str()).clone())
+}
+
+//rich [--difficulty <name>] [--endless <name>] [--record <file> | --replay <file>] [--save <file> | --resume <file>]
+fn replay_mode() -> Option<ReplayMode> {
+    if let Some(file) = flag_value("--record") {
+        return Some(ReplayMode::Record(file));
+    }
+    flag_value("--replay").map(ReplayMode::Play)
+}
+
+fn save_mode() -> Option<SaveMode> {
+    if let Some(file) = flag_value("--save") {
+        return Some(SaveMode::SaveOnExit(file));
+    }
+    flag_value("--resume").map(SaveMode::Resume)
+}
+
 fn main() {
-    App::new()
-        .add_plugin(GamePlugin {})
-        .add_plugin(EditorPlugin {})
-        .run();
+    let mut app = App::new();
+    if let Some(name) = flag_value("--difficulty") {
+        app.insert_resource(DifficultyMode { name });
+    }
+    if let Some(name) = flag_value("--endless") {
+        app.insert_resource(EndlessMode { name });
+    }
+    app.add_plugin(GamePlugin { headless: false })
+        .add_plugin(EditorPlugin {});
+
+    if let Some(mode) = replay_mode() {
+        app.add_plugin(ReplayPlugin { mode });
+    }
+    if let Some(mode) = save_mode() {
+        app.add_plugin(SavePlugin { mode });
+    }
+    app.run();
 }
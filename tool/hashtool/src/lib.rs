@@ -1,101 +1,141 @@
+#[cfg(feature = "save_reverse_hash")]
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::ffi::CStr;
 use std::fs::File;
 use std::io::BufReader;
 use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use xxhash_rust::const_xxh3::xxh3_64 as const_xxh3;
 use xxhash_rust::xxh3::xxh3_64;
 
-static mut TOOL: Option<HashTool> = None;
+//created on first use, every entry point only needs a shared reference, the dictionaries guard themselves
+static TOOL: Lazy<HashTool> = Lazy::new(HashTool::new);
+static STRICT: AtomicBool = AtomicBool::new(false);
 
 #[no_mangle]
 pub extern fn c_get_hash(text: *const c_char) -> u64 {
-    unsafe {
-        if TOOL.is_none() {
-            TOOL = Some(HashTool::new());
-        }
-
-        let text: String = CStr::from_ptr(text).to_str().expect("Can not read string argument.").to_string();
-        return TOOL.as_mut().unwrap().hash(&text);
-    }
+    let text = unsafe { CStr::from_ptr(text) }.to_str().expect("Can not read string argument.");
+    TOOL.hash(text)
 }
 
 #[no_mangle]
 pub extern fn c_save_hash(text: *const c_char) {
-    unsafe {
-        if TOOL.is_none() {
-            return;
+    #[cfg(feature = "save_reverse_hash")]
+        {
+            let path = unsafe { CStr::from_ptr(text) }.to_str().expect("Can not read string argument.");
+            TOOL.save_reverse_dict_2_file(path);
         }
-        #[cfg(feature = "save_reverse_hash")]
-            {
-                let path: String = CStr::from_ptr(text).to_str().expect("Can not read string argument.").to_string();
-                TOOL.as_mut().unwrap().save_reverse_dict_2_file(&path);
-            }
-        TOOL = None;
-    }
+    TOOL.clear();
 }
 
 
 pub fn hash(value: &str) -> u64 {
     if cfg!(feature = "save_reverse_hash") {
-        unsafe {
-            if TOOL.is_none() {
-                TOOL = Some(HashTool::new());
-            }
-            TOOL.as_mut().unwrap().hash(value)
-        }
+        TOOL.hash(value)
     } else {
         HashTool::hash_impl(value)
     }
 }
 
+//in strict mode a recorded collision must fail whatever is loading, e.g. the tables
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+pub fn is_strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+//collisions are only detected while the reverse dictionary is kept
+pub fn collisions() -> Vec<HashCollision> {
+    #[cfg(feature = "save_reverse_hash")]
+        {
+            return TOOL.collisions();
+        }
+    #[cfg(not(feature = "save_reverse_hash"))]
+        {
+            Vec::new()
+        }
+}
+
+//same value as hash, usable in const items and evaluated at compile time, nothing is recorded
+pub const fn const_hash(value: &str) -> u64 {
+    const_xxh3(value.as_bytes())
+}
+
+//records a name hashed by const_hash, so un_hash knows it as well
+pub fn register(id: u64, value: &str) {
+    #[cfg(feature = "save_reverse_hash")]
+        {
+            let mut dict = TOOL.reverse_dict.lock().unwrap();
+            TOOL.record(&mut dict, id, value);
+        }
+    #[cfg(not(feature = "save_reverse_hash"))]
+        {
+            let _ = (id, value);
+        }
+}
+
+//content hash of raw data, never recorded in the reverse dictionary
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    xxh3_64(data)
+}
+
 #[cfg(feature = "save_reverse_hash")]
 pub fn un_hash(hash: u64) -> Option<String> {
-    unsafe {
-        if TOOL.is_none() {
-            None
-        } else {
-            TOOL.as_mut().unwrap().un_hash(hash)
-        }
-    }
+    TOOL.un_hash(hash)
 }
 
 #[cfg(feature = "save_reverse_hash")]
 pub fn load_reverse_dict(path: &str) {
-    unsafe {
-        if TOOL.is_none() {
-            TOOL = Some(HashTool::new());
-        }
-        TOOL.as_mut().unwrap().load_reverse_hash(path);
-    }
+    TOOL.load_reverse_hash(path);
 }
 
 #[cfg(feature = "save_reverse_hash")]
 pub fn save_reverse_dict(path: &str) {
-    unsafe {
-        if TOOL.is_none() {
-            TOOL = Some(HashTool::new());
-        }
-        TOOL.as_mut().unwrap().save_reverse_dict_2_file(path);
-    }
+    TOOL.save_reverse_dict_2_file(path);
 }
 
 #[cfg(feature = "save_reverse_hash")]
 pub fn get_reverse_dict() -> HashMap<u64, String> {
-    unsafe {
-        if TOOL.is_none() {
-            panic!("TOOL is not created")
-        }
-        let t = TOOL.as_mut().unwrap().reverse_dict.lock().unwrap();
-        t.clone()
+    TOOL.reverse_dict.lock().unwrap().clone()
+}
+
+//reads a saved reverse dictionary without merging it into the shared one, e.g. to compare it with a fresh scan
+pub fn read_reverse_dict_file(path: &str) -> std::io::Result<HashMap<u64, String>> {
+    let file = File::open(path)?;
+    let save: HashToolSave = serde_json::de::from_reader(BufReader::new(file))?;
+    Ok(save.data)
+}
+
+pub fn write_reverse_dict_file(path: &str, dict: HashMap<u64, String>) -> std::io::Result<()> {
+    let ret = serde_json::ser::to_string(&HashToolSave { data: dict })?;
+    std::fs::write(path, ret)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashCollision {
+    pub id: u64,
+    pub first: String,
+    pub second: String,
+}
+
+impl std::fmt::Display for HashCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' and '{}' collide on id {}", self.first, self.second, self.id)
     }
 }
 
 pub struct HashTool {
     #[cfg(feature = "save_reverse_hash")]
     pub reverse_dict: Mutex<HashMap<u64, String>>,
+    #[cfg(feature = "save_reverse_hash")]
+    collisions: Mutex<Vec<HashCollision>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -107,7 +147,9 @@ impl HashTool {
     pub fn new() -> Self {
         HashTool {
             #[cfg(feature = "save_reverse_hash")]
-            reverse_dict: Default::default()
+            reverse_dict: Default::default(),
+            #[cfg(feature = "save_reverse_hash")]
+            collisions: Default::default(),
         }
     }
 
@@ -116,16 +158,48 @@ impl HashTool {
         xxh3_64(value.as_bytes())
     }
 
-    pub fn hash(&mut self, value: &str) -> u64 {
+    pub fn hash(&self, value: &str) -> u64 {
         let ret = Self::hash_impl(value);
         #[cfg(feature = "save_reverse_hash")]
             {
                 let mut dict = self.reverse_dict.lock().unwrap();
-                dict.insert(ret, value.to_string());
+                self.record(&mut dict, ret, value);
             }
         ret
     }
 
+    //the first string of an id is kept, a different one for the same id is a collision
+    #[cfg(feature = "save_reverse_hash")]
+    fn record(&self, dict: &mut HashMap<u64, String>, id: u64, value: &str) {
+        match dict.entry(id) {
+            Entry::Occupied(o) => {
+                if o.get() != value {
+                    self.collisions.lock().unwrap().push(HashCollision {
+                        id,
+                        first: o.get().clone(),
+                        second: value.to_string(),
+                    });
+                }
+            }
+            Entry::Vacant(v) => {
+                v.insert(value.to_string());
+            }
+        }
+    }
+
+    #[cfg(feature = "save_reverse_hash")]
+    pub fn collisions(&self) -> Vec<HashCollision> {
+        self.collisions.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        #[cfg(feature = "save_reverse_hash")]
+            {
+                self.reverse_dict.lock().unwrap().clear();
+                self.collisions.lock().unwrap().clear();
+            }
+    }
+
     #[cfg(feature = "save_reverse_hash")]
     pub fn save_reverse_dict_2_file(&self, path: &str) {
         let guard = self.reverse_dict.lock().unwrap();
@@ -136,21 +210,124 @@ impl HashTool {
     }
 
     #[cfg(feature = "save_reverse_hash")]
-    pub fn load_reverse_hash(&mut self, path: &str) {
+    pub fn load_reverse_hash(&self, path: &str) {
         let file = File::open(path).expect(format!("failed to load file at {}", path).as_str());
         let reader = BufReader::new(file);
         let ret: HashToolSave = serde_json::de::from_reader(reader).unwrap();
 
         let mut g = self.reverse_dict.lock().unwrap();
         ret.data.into_iter().for_each(|(k, v)| {
-            g.insert(k, v);
+            self.record(&mut g, k, &v);
         });
     }
 
     #[cfg(feature = "save_reverse_hash")]
-    pub fn un_hash(&mut self, hash: u64) -> Option<String> {
+    pub fn un_hash(&self, hash: u64) -> Option<String> {
         let g = self.reverse_dict.lock().unwrap();
         g.get(&hash).cloned()
     }
 }
 
+#[cfg(all(test, feature = "save_reverse_hash"))]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    #[test]
+    fn collision_keeps_first_string() {
+        let tool = HashTool::new();
+        let id = HashTool::hash_impl("cross_bullet");
+        tool.reverse_dict.lock().unwrap().insert(id, "goblin".to_string());
+
+        assert_eq!(tool.hash("cross_bullet"), id);
+        assert_eq!(tool.un_hash(id), Some("goblin".to_string()));
+        assert_eq!(tool.collisions(), vec![HashCollision { id, first: "goblin".to_string(), second: "cross_bullet".to_string() }]);
+
+        tool.hash("goblin");
+        assert_eq!(tool.collisions().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_hash_shares_one_dictionary() {
+        let tool = std::sync::Arc::new(HashTool::new());
+        let workers = (0..8).map(|worker| {
+            let tool = tool.clone();
+            std::thread::spawn(move || {
+                for i in 0..1000 {
+                    tool.hash(&format!("shared_{}", i));
+                    tool.hash(&format!("worker_{}_{}", worker, i));
+                }
+            })
+        }).collect::<Vec<_>>();
+        workers.into_iter().for_each(|w| w.join().unwrap());
+
+        assert_eq!(tool.reverse_dict.lock().unwrap().len(), 1000 + 8 * 1000);
+        assert_eq!(tool.un_hash(HashTool::hash_impl("worker_7_999")), Some("worker_7_999".to_string()));
+        assert_eq!(tool.collisions(), vec![]);
+    }
+
+    #[test]
+    fn concurrent_hash_through_global_registry() {
+        let workers = (0..8).map(|_| {
+            std::thread::spawn(|| {
+                (0..500).map(|i| {
+                    let name = format!("global_{}", i);
+                    let id = hash(&name);
+                    (name, id)
+                }).collect::<Vec<_>>()
+            })
+        }).collect::<Vec<_>>();
+
+        for worker in workers {
+            for (name, id) in worker.join().unwrap() {
+                assert_eq!(id, HashTool::hash_impl(&name));
+                assert_eq!(un_hash(id), Some(name));
+            }
+        }
+        assert!(collisions().iter().all(|c| !c.first.starts_with("global_")));
+    }
+
+    #[test]
+    fn const_hash_matches_runtime_hash() {
+        const CROSS_BULLET: u64 = const_hash("cross_bullet");
+        assert_eq!(CROSS_BULLET, HashTool::hash_impl("cross_bullet"));
+        assert_eq!(const_hash(""), HashTool::hash_impl(""));
+        let long = "name with-dash and spaces ".repeat(20);
+        assert_eq!(const_hash(&long), HashTool::hash_impl(&long));
+
+        register(CROSS_BULLET, "cross_bullet");
+        assert_eq!(un_hash(CROSS_BULLET), Some("cross_bullet".to_string()));
+    }
+
+    fn collect_ron_strings(dir: &Path, names: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                collect_ron_strings(&path, names);
+            } else if path.extension().map_or(false, |e| e == "ron") {
+                let text = std::fs::read_to_string(&path).unwrap();
+                names.extend(text.split('"').skip(1).step_by(2).map(|s| s.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn no_collision_in_config_names() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/config");
+        let mut names = Vec::new();
+        collect_ron_strings(&config_dir, &mut names);
+
+        let saved: HashToolSave = serde_json::de::from_reader(File::open(config_dir.join("hash.json")).unwrap()).unwrap();
+        for (id, name) in &saved.data {
+            assert_eq!(HashTool::hash_impl(name), *id, "hash.json maps {} to a wrong id", name);
+            names.push(name.clone());
+        }
+        assert!(!names.is_empty());
+
+        let tool = HashTool::new();
+        for name in &names {
+            tool.hash(name);
+        }
+        assert_eq!(tool.collisions(), vec![]);
+    }
+}
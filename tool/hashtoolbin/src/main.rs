@@ -1,42 +1,210 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
-use hashtoollib::HashTool;
-use clap::Parser;
-use game::prelude::*;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
 use anyhow::{Context, Result};
 
+const HASH_JSON: &str = "config/hash.json";
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(short, long)]
-    save_hash_from_config: bool,
+    /// Asset directory holding the tables, the map and hash.json
+    #[clap(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets"))]
+    assets: String,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the id of a name
+    Hash { text: String },
+    /// Look an id up in hash.json
+    Unhash { id: u64 },
+    /// Rebuild hash.json from every table and the map
+    Scan,
+    /// Show the names a scan would add to or remove from hash.json
+    Diff,
+    /// Write a rust file with one constant per scanned name
+    Codegen {
+        #[clap(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../../game/src/str_gen.rs"))]
+        output: PathBuf,
+    },
+    /// Check table and map references, duplicate names and hash collisions
+    Validate,
+    /// Validate, then bake every table into the binary file release builds load
+    Bake,
+}
+
+fn load_map(asset_root: &str) -> Result<(String, game::MapConfig)> {
+    let map_path = format!("{}/{}", asset_root, game::MAP_PATH);
+    let bytes = std::fs::read(&map_path).context(format!("[{}]", &map_path))?;
+    let map = game::load_map_config(&bytes)?;
+    Ok((map_path, map))
+}
+
+fn hash_json_path(asset_root: &str) -> String {
+    format!("{}/{}", asset_root, HASH_JSON)
+}
+
+//the map only keeps unit ids, their names come from the tables or the names the editor saved before
+fn scan(asset_root: &str) -> Result<HashMap<u64, String>> {
+    game::BattleTables::load_from_dir(asset_root)?;
+    let mut dict = hashtoollib::get_reverse_dict();
+
+    let saved = hashtoollib::read_reverse_dict_file(&hash_json_path(asset_root)).unwrap_or_default();
+    let (map_path, map) = load_map(asset_root)?;
+    let mut unknown = Vec::new();
+    for wave in map.wave_queues.iter().flat_map(|q| q.waves.iter()) {
+        if dict.contains_key(&wave.unit) {
+            continue;
+        }
+        match saved.get(&wave.unit) {
+            Some(name) => {
+                dict.insert(wave.unit, name.clone());
+            }
+            None => {
+                unknown.push(wave.unit);
+            }
+        }
+    }
+
+    if !unknown.is_empty() {
+        anyhow::bail!("[{}] unit ids without a known name: {:?}", map_path, unknown);
+    }
+    Ok(dict)
+}
+
+fn diff(asset_root: &str) -> Result<()> {
+    let scanned = scan(asset_root)?;
+    let path = hash_json_path(asset_root);
+    let saved = hashtoollib::read_reverse_dict_file(&path).context(format!("[{}]", &path))?;
+
+    let added = scanned.iter().filter(|(id, _)| !saved.contains_key(id)).collect::<BTreeMap<_, _>>();
+    let removed = saved.iter().filter(|(id, _)| !scanned.contains_key(id)).collect::<BTreeMap<_, _>>();
+    for (id, name) in &added {
+        println!("+ {} ({})", name, id);
+    }
+    for (id, name) in &removed {
+        println!("- {} ({})", name, id);
+    }
+    println!("{} added, {} removed", added.len(), removed.len());
+    Ok(())
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+    "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do",
+    "final", "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+//"ice-arrow 2" becomes ice_arrow_2, names starting with a digit get a leading underscore and keywords a trailing one
+fn sanitize_ident(name: &str) -> String {
+    let mut ident = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect::<String>();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if KEYWORDS.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
 }
 
-fn save_dict_2_rust_file(map: HashMap<u64, String>) -> Result<()> {
-    let full_path  = format!("{}/../../game/src/str_gen.rs", env!("CARGO_MANIFEST_DIR"));
-    let mut f = File::create(&full_path).context(format!("[{}]", &full_path))?;
-    f.write_all(b"use std::collections::HashMap;\n")?;
+fn codegen(asset_root: &str, output: &PathBuf) -> Result<()> {
+    let mut names = scan(asset_root)?.into_values().collect::<Vec<_>>();
+    names.sort();
+    let mut f = File::create(output).context(format!("[{}]", output.display()))?;
+    f.write_all(b"//generated by hashtoolbin codegen, sid!(\"name\") needs no regeneration\n")?;
+    f.write_all(b"#![allow(non_upper_case_globals)]\n")?;
     f.write_all(b"\n")?;
-    for (k, v) in map {
-        let word = format!("pub const {}:u64 = {};\n", v, k);
+
+    let mut used = HashSet::new();
+    for name in &names {
+        let base = sanitize_ident(name);
+        let mut ident = base.clone();
+        let mut suffix = 2;
+        while !used.insert(ident.clone()) {
+            ident = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        let word = format!("pub const {}: u64 = hashtoollib::const_hash({:?});\n", ident, name);
         f.write_all(word.as_bytes())?;
     }
 
     Ok(())
 }
 
-fn main() {
-    println!("<hashlib tool>");
-    let mut app = App::new();
-    game::load_battle_tables(&mut app);
-    let dict = hashtoollib::get_reverse_dict();
-    match save_dict_2_rust_file(dict) {
-        Ok(_) => {
-            println!("save complete");
+fn validate(asset_root: &str) -> Result<game::BattleTables> {
+    let tables = game::BattleTables::load_from_dir(asset_root)?;
+    let (map_path, map) = load_map(asset_root)?;
+
+    let report = tables.validate(Some((&map_path, &map)));
+    if !report.is_ok() {
+        anyhow::bail!("{}", report);
+    }
+    Ok(tables)
+}
+
+fn bake(asset_root: &str) -> Result<()> {
+    let tables = validate(asset_root)?;
+    tables.save_baked()?;
+    Ok(())
+}
+
+fn run(args: Args) -> Result<()> {
+    let asset_root = args.assets.as_str();
+    match args.command {
+        Command::Hash { text } => {
+            println!("{}", hashtoollib::hash(&text));
+        }
+        Command::Unhash { id } => {
+            let path = hash_json_path(asset_root);
+            let saved = hashtoollib::read_reverse_dict_file(&path).context(format!("[{}]", &path))?;
+            match saved.get(&id) {
+                Some(name) => {
+                    println!("{}", name);
+                }
+                None => {
+                    anyhow::bail!("id {} not found in {}", id, path);
+                }
+            }
+        }
+        Command::Scan => {
+            let dict = scan(asset_root)?;
+            let path = hash_json_path(asset_root);
+            let count = dict.len();
+            hashtoollib::write_reverse_dict_file(&path, dict).context(format!("[{}]", &path))?;
+            println!("scan complete, {} names saved", count);
+        }
+        Command::Diff => {
+            diff(asset_root)?;
+        }
+        Command::Codegen { output } => {
+            codegen(asset_root, &output)?;
+            println!("codegen complete");
+        }
+        Command::Validate => {
+            validate(asset_root)?;
+            println!("validate complete, no issue found");
         }
-        Err(e) => {
-            panic!("error: {:?}", e);
+        Command::Bake => {
+            bake(asset_root)?;
+            println!("bake complete");
         }
     }
+    Ok(())
+}
+
+fn main() {
+    eprintln!("<hashlib tool>");
+    let args = Args::parse();
+    hashtoollib::set_strict(true);
+
+    if let Err(e) = run(args) {
+        panic!("error: {:?}", e);
+    }
 }